@@ -0,0 +1,194 @@
+//! Validation of a configuration file without running it. The file is
+//! parsed and checked, and optionally the devices it defines are
+//! looked up in the system, along with the attributes backing their
+//! sensors and outputs. Nothing is ever written to the devices.
+
+use crate::config::{
+    self, ast, checker::model as cmodel, SensorSelector, SymbolDevice, SymbolOutput, SymbolSensor,
+};
+use crate::device::{driver_registry_find, DeviceDiscovery};
+use guard::guard;
+use std::{path::PathBuf, rc::Rc};
+
+/// The configuration is valid, and every device and attribute has
+/// been found, if verified.
+pub const EXIT_OK: i32 = 0;
+
+/// The configuration cannot be read, or it has errors.
+pub const EXIT_INVALID: i32 = 1;
+
+/// The configuration is valid, but some device or attribute it refers
+/// to cannot be found in the system.
+pub const EXIT_UNRESOLVED: i32 = 2;
+
+/// Looks up the given device, and each of its sensors and outputs,
+/// printing the outcome. Returns whether everything was found.
+fn verify_device(
+    program: &cmodel::ThermalProgram,
+    discovery: &DeviceDiscovery,
+    device: &Rc<SymbolDevice>,
+) -> bool {
+    let mut sensors = program
+        .symbol_table
+        .get_all_symbols_of_type::<SymbolSensor>()
+        .into_iter()
+        .filter(|sensor| Rc::ptr_eq(&sensor.device, device))
+        .collect::<Vec<_>>();
+    sensors.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut outputs = program
+        .symbol_table
+        .get_all_symbols_of_type::<SymbolOutput>()
+        .into_iter()
+        .filter(|output| Rc::ptr_eq(&output.device, device))
+        .collect::<Vec<_>>();
+    outputs.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let location = match discovery.find(&device.tag) {
+        Some(location) => location,
+        None => {
+            println!(
+                "device `{}` (tag \"{}\"): NOT FOUND{}",
+                device.name,
+                device.tag,
+                if device.allow_hotplug {
+                    ", allowed as it is hot-pluggable"
+                } else {
+                    ""
+                }
+            );
+            for sensor in sensors {
+                println!("  sensor `{}`: skipped", sensor.name);
+            }
+            for output in outputs {
+                println!("  output `{}`: skipped", output.name);
+            }
+            return device.allow_hotplug;
+        }
+    };
+
+    println!(
+        "device `{}` (tag \"{}\"): found at {}",
+        device.name,
+        device.tag,
+        location.path().display()
+    );
+
+    // The device is built on dry run mode, so nothing can be written
+    // to it even by mistake.
+    let online = crate::create_device(&device.driver, device.name.clone(), location, true);
+
+    let mut all_found = true;
+    let mut report = |kind: &str, name: &str, path: std::io::Result<PathBuf>| match path {
+        Ok(path) => {
+            let found = path.exists();
+            all_found &= found;
+            println!(
+                "  {} `{}`: {} {}",
+                kind,
+                name,
+                if found { "ok" } else { "MISSING" },
+                path.display()
+            );
+        }
+        Err(err) => {
+            all_found = false;
+            println!("  {} `{}`: MISSING {}", kind, name, err);
+        }
+    };
+
+    for sensor in sensors {
+        let index = match &sensor.selector {
+            SensorSelector::Index(index) => Ok(*index),
+            SensorSelector::Zone(zone_type) => online.find_zone(zone_type),
+            SensorSelector::Label(label) => online.find_temp_by_label(label),
+        };
+        let path = index.map(|index| match sensor.sensor_type {
+            ast::SensorType::Termistor => online.temp_input_path(index),
+            ast::SensorType::Fan => online.fan_input_path(index),
+        });
+        report("sensor", &sensor.name, path);
+    }
+
+    for output in outputs {
+        report("output", &output.name, Ok(online.pwm_path(output.index)));
+    }
+
+    all_found
+}
+
+/// Checks the given configuration file, printing a report of the
+/// outcome, and returns the status the program should exit with. If
+/// a way of discovering the devices is given, the devices are looked
+/// up too.
+pub fn run(config_path: &str, discovery: Option<&DeviceDiscovery>) -> i32 {
+    let source = match std::fs::read_to_string(config_path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("Cannot read {}: {}", config_path, err);
+            return EXIT_INVALID;
+        }
+    };
+
+    let (program, warnings) = match config::compile(config_path, &source) {
+        Ok(compiled) => compiled,
+        Err(err) => {
+            eprintln!("{}", err);
+            return EXIT_INVALID;
+        }
+    };
+
+    let mut devices = program
+        .symbol_table
+        .get_all_symbols_of_type::<SymbolDevice>();
+    devices.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let unknown_drivers = devices
+        .iter()
+        .filter(|device| driver_registry_find(&device.driver).is_none())
+        .collect::<Vec<_>>();
+    if !unknown_drivers.is_empty() {
+        for device in unknown_drivers {
+            eprintln!(
+                "error: Unknown driver \"{}\" for device `{}`",
+                device.driver, device.name
+            );
+        }
+        return EXIT_INVALID;
+    }
+
+    for warning in warnings.iter() {
+        eprintln!("{}\n", warning);
+    }
+
+    println!(
+        "{}: OK ({} devices, {} sensors, {} outputs, {} rules, {} warnings)",
+        config_path,
+        devices.len(),
+        program
+            .symbol_table
+            .get_all_symbols_of_type::<SymbolSensor>()
+            .len(),
+        program
+            .symbol_table
+            .get_all_symbols_of_type::<SymbolOutput>()
+            .len(),
+        program.rules.len(),
+        warnings.len()
+    );
+
+    guard!(let Some(discovery) = discovery else {
+        return EXIT_OK;
+    });
+
+    let mut all_found = true;
+    for device in devices {
+        all_found &= verify_device(&program, discovery, device);
+    }
+
+    if all_found {
+        EXIT_OK
+    } else {
+        EXIT_UNRESOLVED
+    }
+}
@@ -1,4 +1,37 @@
-use std::fmt::Debug;
+use std::fmt::{Debug, Display};
+use std::ops::Deref;
+use std::time::Duration;
+
+/// Location of a node on the source of the program, as a range of
+/// byte offsets.
+#[derive(new, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A value along with the location of the source it was parsed from.
+#[derive(new, Debug, Clone)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Deref for Spanned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.node
+    }
+}
+
+/// Error raised by the actions of the grammar, such as a number that
+/// does not fit on its type.
+#[derive(new, Debug, Clone)]
+pub struct SyntaxError {
+    pub message: String,
+    pub span: Span,
+}
 
 #[derive(new, Debug, Clone)]
 pub struct Program {
@@ -7,27 +40,133 @@ pub struct Program {
 
 #[derive(new, Debug, Clone)]
 pub struct RuleDefineDevice {
-    pub dev_name: String,
+    pub dev_name: Spanned<String>,
     pub udev_tag: String,
     pub driver_name: String,
     pub allow_hotplug: bool,
+    pub failure_policy: Option<FailurePolicy>,
+}
+
+#[derive(new, Debug, Clone)]
+pub struct FailurePolicy {
+    pub action: FailureAction,
+    pub retries: Option<Spanned<i32>>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum FailureAction {
+    Full,
+    Keep,
+    Auto,
+}
+
+impl Default for FailureAction {
+    fn default() -> Self {
+        Self::Full
+    }
+}
+
+impl Display for FailureAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FailureAction::Full => write!(f, "FULL"),
+            FailureAction::Keep => write!(f, "KEEP"),
+            FailureAction::Auto => write!(f, "AUTO"),
+        }
+    }
 }
 
 #[derive(new, Debug, Clone)]
 pub struct RuleDefineSensor {
-    pub sensor_name: String,
-    pub device: String,
+    pub sensor_name: Spanned<String>,
+    pub device: Spanned<String>,
     pub sensor_type: SensorType,
-    pub index: i32,
+    pub selector: SensorSelector,
+}
+
+/// How a sensor is selected among the ones of its device.
+#[derive(Debug, Clone)]
+pub enum SensorSelector {
+    Index(Spanned<i32>),
+
+    /// The type of a thermal zone.
+    Zone(Spanned<String>),
+
+    /// The label of a temperature, as in the `tempN_label` attributes.
+    Label(Spanned<String>),
+}
+
+#[derive(new, Debug, Clone)]
+pub struct RuleDefineAggregate {
+    pub sensor_name: Spanned<String>,
+    pub function: AggregateFunction,
+    pub inputs: Vec<AggregateInput>,
+    pub span: Span,
+}
+
+#[derive(new, Debug, Clone)]
+pub struct AggregateInput {
+    pub sensor: Spanned<String>,
+    pub weight: Option<Spanned<i32>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateFunction {
+    Min,
+    Max,
+    Avg,
+}
+
+impl Display for AggregateFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AggregateFunction::Min => write!(f, "MIN"),
+            AggregateFunction::Max => write!(f, "MAX"),
+            AggregateFunction::Avg => write!(f, "AVG"),
+        }
+    }
 }
 
 #[derive(new, Debug, Clone)]
 pub struct RuleDefineOutput {
-    pub output_name: String,
-    pub device: String,
+    pub output_name: Spanned<String>,
+    pub device: Spanned<String>,
     pub output_type: OutputType,
-    pub index: i32,
+    pub index: Spanned<i32>,
     pub priorization: OutputPriorization,
+    pub tachometer: Option<Spanned<String>>,
+    pub ramp_up: Option<Spanned<i32>>,
+    pub ramp_down: Option<Spanned<i32>>,
+    pub limits: OutputLimits,
+}
+
+#[derive(new, Debug, Clone, Default)]
+pub struct OutputLimits {
+    pub min: Option<Spanned<i32>>,
+    pub max: Option<Spanned<i32>>,
+    pub stop_below: Option<Spanned<i32>>,
+    pub spin_up: Option<SpinUp>,
+}
+
+#[derive(new, Debug, Clone)]
+pub struct SpinUp {
+    pub value: Spanned<i32>,
+    pub duration: Spanned<Duration>,
+}
+
+#[derive(new, Debug, Clone)]
+pub struct StallAlarm {
+    pub threshold: Spanned<i32>,
+    pub grace_period: Duration,
+    pub actions: Vec<AlarmAction>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum AlarmAction {
+    Exec(String),
+    SetDeviceFull,
 }
 
 #[derive(Debug, Clone)]
@@ -47,6 +186,7 @@ impl Default for OutputPriorization {
 pub enum RuleDefine {
     Device(RuleDefineDevice),
     Sensor(RuleDefineSensor),
+    Aggregate(RuleDefineAggregate),
     Output(RuleDefineOutput),
 }
 
@@ -55,6 +195,7 @@ impl Debug for RuleDefine {
         match self {
             RuleDefine::Device(device) => device.fmt(f),
             RuleDefine::Sensor(sensor) => sensor.fmt(f),
+            RuleDefine::Aggregate(aggregate) => aggregate.fmt(f),
             RuleDefine::Output(output) => output.fmt(f),
         }
     }
@@ -62,19 +203,24 @@ impl Debug for RuleDefine {
 
 #[derive(Debug, Clone)]
 pub enum OutputValue {
-    Between(i32, i32),
-    Fixed(i32),
+    Between(Spanned<i32>, Spanned<i32>),
+    Fixed(Spanned<i32>),
+
+    /// Piecewise-linear curve, defined by a list of (sensor value,
+    /// percent) points.
+    Curve(Vec<(Quantity, Spanned<i32>)>),
 }
 
 #[derive(new, Debug, Clone)]
 pub struct WhenActionOutputSet {
-    pub target_output: String,
+    pub target_output: Spanned<String>,
     pub value: OutputValue,
+    pub span: Span,
 }
 
 #[derive(Clone)]
 pub enum WhenAction {
-    Log,
+    Log(Span),
     OutputSet(WhenActionOutputSet),
 }
 
@@ -82,30 +228,70 @@ impl Debug for WhenAction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             WhenAction::OutputSet(set) => set.fmt(f),
-            WhenAction::Log => write!(f, "Log"),
+            WhenAction::Log(_) => write!(f, "Log"),
         }
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+pub enum Unit {
+    Celsius,
+    Fahrenheit,
+    Kelvin,
+    Rpm,
+}
+
+impl Display for Unit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Unit::Celsius => write!(f, "C"),
+            Unit::Fahrenheit => write!(f, "F"),
+            Unit::Kelvin => write!(f, "K"),
+            Unit::Rpm => write!(f, "RPM"),
+        }
+    }
+}
+
+/// A number compared against the value of a sensor, possibly with
+/// decimals. If no unit is specified, the number is expressed in the
+/// default unit of the type of the sensor.
+#[derive(new, Debug, Clone)]
+pub struct Quantity {
+    pub value: f64,
+    pub unit: Option<Unit>,
+    pub span: Span,
+}
+
 #[derive(new, Debug, Clone)]
 pub enum WhenCondition {
-    Between(i32, i32),
-    GreaterThan(i32),
-    LessThan(i32),
+    Between(Quantity, Quantity),
+    GreaterThan(Quantity),
+    LessThan(Quantity),
 }
 
 #[derive(new, Debug, Clone)]
 pub struct RuleWhen {
     pub tag: Option<String>,
-    pub sensor: String,
+    pub sensor: Spanned<String>,
     pub condition: WhenCondition,
+
+    /// Time the condition must hold before triggering the rule.
+    pub hold: Option<Duration>,
+
+    /// Time the rule is kept triggered after its condition stops
+    /// holding.
+    pub release: Option<Duration>,
+    pub hysteresis: Option<Quantity>,
     pub actions: Vec<WhenAction>,
+    pub span: Span,
 }
 
 #[derive(Clone)]
 pub enum Rule {
     Define(RuleDefine),
     When(RuleWhen),
+    FailurePolicy(FailurePolicy),
+    StallAlarm(StallAlarm),
 }
 
 impl Debug for Rule {
@@ -113,16 +299,27 @@ impl Debug for Rule {
         match self {
             Rule::Define(define) => define.fmt(f),
             Rule::When(when) => when.fmt(f),
+            Rule::FailurePolicy(policy) => policy.fmt(f),
+            Rule::StallAlarm(alarm) => alarm.fmt(f),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SensorType {
     Termistor,
     Fan,
 }
 
+impl Display for SensorType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SensorType::Termistor => write!(f, "TERMISTOR"),
+            SensorType::Fan => write!(f, "FAN"),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum OutputType {
     Pwm,
@@ -2,78 +2,427 @@ use std::convert::TryInto;
 
 use model::OutputValue;
 
-use super::{model, NumBoundary, ProgramCheckError, ProgramCheckResult, SemanticError};
-use crate::config::{ast, Symbol, SymbolDevice, SymbolOutput, SymbolSensor, SymbolTable};
-use crate::types::Percent;
-use std::convert::TryFrom;
+use super::{model, AtSpan, CheckError, NumBoundary, ProgramCheckResult, SemanticError};
+use crate::config::{
+    ast::{self, Span, Spanned},
+    RuleSensor, SensorSelector, Symbol, SymbolAggregate, SymbolDevice, SymbolOutput, SymbolSensor,
+    SymbolTable,
+};
+use crate::types::{Percent, Rpm, SensorValue, TempCelsius, TempFahrenheit};
+use std::{convert::TryFrom, rc::Rc, time::Duration};
+
+fn process_failure_policy(policy: ast::FailurePolicy) -> ProgramCheckResult<model::FailurePolicy> {
+    let retries = match policy.retries {
+        Some(retries) => (*retries).try_into().map_err(|_| {
+            SemanticError::NumberOutOfBounds(NumBoundary::GreaterOrEqual(0.0), *retries as f64)
+                .at(retries.span)
+        })?,
+        None => 0,
+    };
+
+    Ok(model::FailurePolicy::new(policy.action, retries))
+}
 
 fn process_define_rule(
     sym_table: &mut SymbolTable,
     rule: ast::RuleDefine,
 ) -> ProgramCheckResult<()> {
     (match rule {
-        ast::RuleDefine::Device(device) => sym_table
-            .insert(
-                device.dev_name.clone(),
-                Symbol::Device(
-                    SymbolDevice::new(
-                        device.dev_name,
-                        device.udev_tag,
-                        device.driver_name,
-                        device.allow_hotplug,
-                    )
-                    .into(),
-                ),
-            )
-            .map_err(|err| err.into()),
+        ast::RuleDefine::Device(device) => {
+            let failure_policy = device
+                .failure_policy
+                .map(process_failure_policy)
+                .transpose()?;
+
+            sym_table
+                .insert(
+                    device.dev_name.node.clone(),
+                    Symbol::Device(
+                        SymbolDevice::new(
+                            device.dev_name.node.clone(),
+                            device.udev_tag,
+                            device.driver_name,
+                            device.allow_hotplug,
+                            failure_policy,
+                        )
+                        .into(),
+                    ),
+                )
+                .at(device.dev_name.span)
+        }
         ast::RuleDefine::Sensor(sensor) => {
-            let device = sym_table.require_type::<SymbolDevice>(&sensor.device)?;
+            let device = sym_table
+                .require_type::<SymbolDevice>(&sensor.device)
+                .at(sensor.device.span)?;
+
+            let selector = match sensor.selector {
+                ast::SensorSelector::Index(index) => {
+                    SensorSelector::Index((*index).try_into().map_err(|_| {
+                        SemanticError::NumberOutOfBounds(
+                            NumBoundary::BetweenBothExclusive(0.0, 255.0),
+                            *index as f64,
+                        )
+                        .at(index.span)
+                    })?)
+                }
+                ast::SensorSelector::Zone(name) | ast::SensorSelector::Label(name)
+                    if sensor.sensor_type != ast::SensorType::Termistor =>
+                {
+                    return Err(SemanticError::NamedNonTemperature(
+                        sensor.sensor_name.node.clone(),
+                    )
+                    .at(name.span));
+                }
+                ast::SensorSelector::Zone(zone) => SensorSelector::Zone(zone.node),
+                ast::SensorSelector::Label(label) => SensorSelector::Label(label.node),
+            };
 
             let symbol = Symbol::Sensor(
                 SymbolSensor::new(
-                    sensor.sensor_name.clone(),
+                    sensor.sensor_name.node.clone(),
                     sensor.sensor_type,
-                    sensor.index,
+                    selector,
                     device.clone(),
                 )
                 .into(),
             );
 
             sym_table
-                .insert(sensor.sensor_name, symbol)
-                .map_err(|err| err.into())
+                .insert(sensor.sensor_name.node, symbol)
+                .at(sensor.sensor_name.span)
+        }
+        ast::RuleDefine::Aggregate(aggregate) => {
+            if aggregate.inputs.is_empty() {
+                return Err(SemanticError::EmptyAggregate.at(aggregate.span));
+            }
+
+            let mut inputs: Vec<(Rc<SymbolSensor>, u32)> =
+                Vec::with_capacity(aggregate.inputs.len());
+            for input in aggregate.inputs.iter() {
+                let sensor = sym_table
+                    .require_type::<SymbolSensor>(&input.sensor)
+                    .at(input.sensor.span)?;
+
+                if let Some((first, _)) = inputs.first() {
+                    if sensor.sensor_type != first.sensor_type {
+                        return Err(SemanticError::MixedAggregate {
+                            sensor: sensor.name.clone(),
+                            sensor_type: sensor.sensor_type,
+                            expected: first.sensor_type,
+                        }
+                        .at(input.sensor.span));
+                    }
+                }
+
+                let weight = match &input.weight {
+                    Some(weight) if aggregate.function != ast::AggregateFunction::Avg => {
+                        return Err(SemanticError::WeightOutsideAvg.at(weight.span))
+                    }
+                    Some(weight) if **weight <= 0 => {
+                        return Err(SemanticError::NumberOutOfBounds(
+                            NumBoundary::Greater(0.0),
+                            **weight as f64,
+                        )
+                        .at(weight.span))
+                    }
+                    Some(weight) => **weight as u32,
+                    None => 1,
+                };
+
+                inputs.push((sensor.clone(), weight));
+            }
+
+            let symbol = Symbol::Aggregate(
+                SymbolAggregate::new(
+                    aggregate.sensor_name.node.clone(),
+                    inputs[0].0.sensor_type,
+                    aggregate.function,
+                    inputs,
+                )
+                .into(),
+            );
+
+            sym_table
+                .insert(aggregate.sensor_name.node, symbol)
+                .at(aggregate.sensor_name.span)
         }
         ast::RuleDefine::Output(output) => {
-            let device = sym_table.require_type::<SymbolDevice>(&output.device)?;
+            let device = sym_table
+                .require_type::<SymbolDevice>(&output.device)
+                .at(output.device.span)?;
 
-            let output_index: u8 = output.index.try_into().map_err(|_| {
-                ProgramCheckError::SemanticError(SemanticError::NumberOutOfBounds(
-                    NumBoundary::BetweenBothExclusive(0, 255),
-                    output.index,
-                ))
+            let output_index: u8 = (*output.index).try_into().map_err(|_| {
+                SemanticError::NumberOutOfBounds(
+                    NumBoundary::BetweenBothExclusive(0.0, 255.0),
+                    *output.index as f64,
+                )
+                .at(output.index.span)
             })?;
 
+            let tachometer = match &output.tachometer {
+                Some(name) => {
+                    let sensor = sym_table.require_type::<SymbolSensor>(name).at(name.span)?;
+                    if sensor.sensor_type != ast::SensorType::Fan {
+                        return Err(
+                            SemanticError::TachometerNotFan(name.node.clone()).at(name.span)
+                        );
+                    }
+                    Some(sensor.clone())
+                }
+                None => None,
+            };
+
+            let ramp_up = output.ramp_up.as_ref().map(cast_ramp_rate).transpose()?;
+            let ramp_down = output.ramp_down.as_ref().map(cast_ramp_rate).transpose()?;
+            let limits = process_output_limits(&output.limits)?;
+
             let symbol: Symbol = Symbol::Output(
                 SymbolOutput::new(
-                    output.output_name.clone(),
+                    output.output_name.node.clone(),
                     device.clone(),
                     output.output_type,
                     output_index,
                     output.priorization,
+                    tachometer,
+                    ramp_up,
+                    ramp_down,
+                    limits,
                 )
                 .into(),
             );
             sym_table
-                .insert(output.output_name, symbol)
-                .map_err(|err| err.into())
+                .insert(output.output_name.node, symbol)
+                .at(output.output_name.span)
         }
     })
     .map(|_| ())
 }
 
-fn cast_percent(value: i32) -> ProgramCheckResult<Percent> {
-    Percent::try_from(value)
-        .map_err(|err| ProgramCheckError::SemanticError(SemanticError::InvalidPercent(value)))
+fn cast_percent(value: &Spanned<i32>) -> ProgramCheckResult<Percent> {
+    Percent::try_from(**value).map_err(|_| SemanticError::InvalidPercent(**value).at(value.span))
+}
+
+fn cast_ramp_rate(rate: &Spanned<i32>) -> ProgramCheckResult<u32> {
+    if **rate <= 0 {
+        return Err(
+            SemanticError::NumberOutOfBounds(NumBoundary::Greater(0.0), **rate as f64)
+                .at(rate.span),
+        );
+    }
+
+    Ok(**rate as u32)
+}
+
+fn process_output_limits(limits: &ast::OutputLimits) -> ProgramCheckResult<model::OutputLimits> {
+    let cast = |limit: &Option<Spanned<i32>>| limit.as_ref().map(cast_percent).transpose();
+    let min = cast(&limits.min)?;
+    let max = cast(&limits.max)?;
+    let stop_below = cast(&limits.stop_below)?;
+
+    let spin_up = match &limits.spin_up {
+        Some(spin_up) => {
+            if *spin_up.value <= 0 {
+                return Err(SemanticError::NumberOutOfBounds(
+                    NumBoundary::Greater(0.0),
+                    *spin_up.value as f64,
+                )
+                .at(spin_up.value.span));
+            }
+            if *spin_up.duration == Duration::ZERO {
+                return Err(
+                    SemanticError::NumberOutOfBounds(NumBoundary::Greater(0.0), 0.0)
+                        .at(spin_up.duration.span),
+                );
+            }
+            Some((cast_percent(&spin_up.value)?, *spin_up.duration))
+        }
+        None => None,
+    };
+
+    // Sorted by the order the values must follow.
+    let ordered = [
+        (
+            "STOP BELOW",
+            stop_below,
+            limits.stop_below.as_ref().map(|l| l.span),
+        ),
+        ("MIN", min, limits.min.as_ref().map(|l| l.span)),
+        (
+            "START AT",
+            spin_up.map(|(value, _)| value),
+            limits.spin_up.as_ref().map(|s| s.value.span),
+        ),
+        ("MAX", max, limits.max.as_ref().map(|l| l.span)),
+    ];
+    for (index, &(limit, value, span)) in ordered.iter().enumerate() {
+        for &(other, other_value, _) in ordered[index + 1..].iter() {
+            if let (Some(value), Some(other_value), Some(span)) = (value, other_value, span) {
+                if value > other_value {
+                    return Err(
+                        SemanticError::ConflictingLimits(limit, value, other, other_value).at(span),
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(model::OutputLimits::new(min, max, stop_below, spin_up))
+}
+
+/// Highest temperature accepted on the configuration, in degrees
+/// Celsius. Keeps every temperature, even once widened by a
+/// hysteresis as high, within the range of millicelsius.
+const MAX_TEMPERATURE: f64 = 1_000_000.0;
+
+/// Returns the highest temperature accepted on the configuration, in
+/// the given unit.
+fn max_temperature(unit: ast::Unit) -> f64 {
+    match unit {
+        ast::Unit::Fahrenheit => MAX_TEMPERATURE * 9.0 / 5.0 + 32.0,
+        ast::Unit::Kelvin => MAX_TEMPERATURE + 273.15,
+        _ => MAX_TEMPERATURE,
+    }
+}
+
+/// Returns the absolute zero in the given unit of temperature.
+fn absolute_zero(unit: ast::Unit) -> f64 {
+    match unit {
+        ast::Unit::Fahrenheit => -459.67,
+        ast::Unit::Kelvin => 0.0,
+        _ => -273.15,
+    }
+}
+
+/// Converts a temperature in the given unit into millicelsius,
+/// checking that it is within the range of accepted temperatures.
+fn cast_temperature(unit: ast::Unit, quantity: &ast::Quantity) -> ProgramCheckResult<TempCelsius> {
+    let value = quantity.value;
+    let min = absolute_zero(unit);
+    if value < min {
+        return Err(
+            SemanticError::NumberOutOfBounds(NumBoundary::GreaterOrEqual(min), value)
+                .at(quantity.span),
+        );
+    }
+
+    let max = max_temperature(unit);
+    if value > max {
+        return Err(
+            SemanticError::NumberOutOfBounds(NumBoundary::LessOrEqual(max), value)
+                .at(quantity.span),
+        );
+    }
+
+    Ok(match unit {
+        ast::Unit::Fahrenheit => TempFahrenheit::from_fahrenheit(value as f32).into(),
+        ast::Unit::Kelvin => TempCelsius::from_mcelsius(((value - 273.15) * 1000.0).round() as i32),
+        _ => TempCelsius::from_mcelsius((value * 1000.0).round() as i32),
+    })
+}
+
+/// Converts a quantity into a value comparable against the given
+/// sensor, checking that its unit is compatible with the type of the
+/// sensor.
+fn cast_sensor_value(
+    sensor: &RuleSensor,
+    quantity: ast::Quantity,
+) -> ProgramCheckResult<SensorValue> {
+    let value = quantity.value;
+    match (sensor.sensor_type(), quantity.unit) {
+        (ast::SensorType::Termistor, None) => {
+            cast_temperature(ast::Unit::Celsius, &quantity).map(SensorValue::Temp)
+        }
+        (ast::SensorType::Termistor, Some(unit @ ast::Unit::Celsius))
+        | (ast::SensorType::Termistor, Some(unit @ ast::Unit::Fahrenheit))
+        | (ast::SensorType::Termistor, Some(unit @ ast::Unit::Kelvin)) => {
+            cast_temperature(unit, &quantity).map(SensorValue::Temp)
+        }
+        (ast::SensorType::Fan, None) | (ast::SensorType::Fan, Some(ast::Unit::Rpm)) => {
+            if value.fract() != 0.0 {
+                return Err(SemanticError::NonIntegerRpm(value).at(quantity.span));
+            }
+            if value < 0.0 {
+                return Err(SemanticError::NumberOutOfBounds(
+                    NumBoundary::GreaterOrEqual(0.0),
+                    value,
+                )
+                .at(quantity.span));
+            }
+            if value > i32::MAX as f64 {
+                return Err(SemanticError::NumberOutOfBounds(
+                    NumBoundary::LessOrEqual(i32::MAX as f64),
+                    value,
+                )
+                .at(quantity.span));
+            }
+            Ok(SensorValue::Fan(Rpm::from_rpm(value as u32)))
+        }
+        (sensor_type, Some(unit)) => Err(SemanticError::IncompatibleUnit {
+            sensor: sensor.name().to_string(),
+            sensor_type,
+            unit,
+        }
+        .at(quantity.span)),
+    }
+}
+
+/// Converts a quantity expressing a difference between two values of
+/// the given sensor, such as a hysteresis, into the base unit of the
+/// sensor. Unlike absolute temperatures, a difference of one kelvin is
+/// the same as one of one degree Celsius.
+fn cast_sensor_delta(sensor: &RuleSensor, quantity: ast::Quantity) -> ProgramCheckResult<i32> {
+    if quantity.value < 0.0 {
+        return Err(SemanticError::NumberOutOfBounds(
+            NumBoundary::GreaterOrEqual(0.0),
+            quantity.value,
+        )
+        .at(quantity.span));
+    }
+
+    let (max, mcelsius_per_unit) = match (sensor.sensor_type(), quantity.unit) {
+        (ast::SensorType::Termistor, None)
+        | (ast::SensorType::Termistor, Some(ast::Unit::Celsius))
+        | (ast::SensorType::Termistor, Some(ast::Unit::Kelvin)) => (MAX_TEMPERATURE, 1000.0),
+        (ast::SensorType::Termistor, Some(ast::Unit::Fahrenheit)) => {
+            (MAX_TEMPERATURE * 9.0 / 5.0, 5000.0 / 9.0)
+        }
+        _ => return cast_sensor_value(sensor, quantity).map(SensorValue::raw),
+    };
+
+    if quantity.value > max {
+        return Err(SemanticError::NumberOutOfBounds(
+            NumBoundary::LessOrEqual(max),
+            quantity.value,
+        )
+        .at(quantity.span));
+    }
+
+    Ok((quantity.value * mcelsius_per_unit).round() as i32)
+}
+
+fn cast_curve(
+    sensor: &RuleSensor,
+    points: Vec<(ast::Quantity, Spanned<i32>)>,
+    span: Span,
+) -> ProgramCheckResult<model::Curve> {
+    if points.len() < 2 {
+        return Err(SemanticError::CurveTooShort(points.len()).at(span));
+    }
+
+    let mut curve_points: Vec<(SensorValue, Percent)> = Vec::with_capacity(points.len());
+    for (quantity, value) in points {
+        let point_span = quantity.span;
+        let point = cast_sensor_value(sensor, quantity)?;
+        if let Some(&(prev, _)) = curve_points.last() {
+            if point <= prev {
+                return Err(SemanticError::NonMonotonicCurve(prev, point).at(point_span));
+            }
+        }
+
+        curve_points.push((point, cast_percent(&value)?));
+    }
+
+    Ok(model::Curve::new(curve_points))
 }
 
 fn process_when_rule(
@@ -81,22 +430,29 @@ fn process_when_rule(
     rule_index: u32,
     rule: ast::RuleWhen,
 ) -> ProgramCheckResult<model::When> {
-    fn into_fixed_actions(
-        actions: Vec<model::Action<model::OutputSetGeneric>>,
-    ) -> ProgramCheckResult<Vec<model::Action<model::OutputSetFixed>>> {
+    fn into_unbounded_actions(
+        actions: Vec<(model::Action<model::OutputSetGeneric>, Span)>,
+    ) -> ProgramCheckResult<Vec<model::Action<model::OutputSetUnbounded>>> {
         let mut result = Vec::new();
 
-        for action in actions {
+        for (action, span) in actions {
             match action {
                 model::Action::OutputSet(action) => match action.value {
                     OutputValue::Between(_, _) => {
-                        return Err(ProgramCheckError::SemanticError(
-                            SemanticError::BetweenActionInUnboundedRule,
-                        ))
+                        return Err(SemanticError::BetweenActionInUnboundedRule.at(span))
+                    }
+                    OutputValue::Fixed(value) => {
+                        result.push(model::Action::OutputSet(model::OutputSetUnbounded::new(
+                            action.target,
+                            model::UnboundedOutputValue::Fixed(value),
+                        )))
+                    }
+                    OutputValue::Curve(curve) => {
+                        result.push(model::Action::OutputSet(model::OutputSetUnbounded::new(
+                            action.target,
+                            model::UnboundedOutputValue::Curve(curve),
+                        )))
                     }
-                    OutputValue::Fixed(value) => result.push(model::Action::OutputSet(
-                        model::OutputSetFixed::new(action.target, value),
-                    )),
                 },
                 model::Action::Log => result.push(model::Action::Log),
             }
@@ -105,71 +461,300 @@ fn process_when_rule(
         Ok(result)
     }
 
-    let sensor = sym_table.require_type::<SymbolSensor>(&rule.sensor)?;
+    let sensor = &sym_table
+        .require_rule_sensor(&rule.sensor)
+        .at(rule.sensor.span)?;
 
+    // The location of each action is kept until the type of the rule
+    // is known, for reporting the actions that do not fit the rule.
     let mut actions =
-        Vec::<model::Action<model::OutputSetGeneric>>::with_capacity(rule.actions.len());
+        Vec::<(model::Action<model::OutputSetGeneric>, Span)>::with_capacity(rule.actions.len());
     for action in rule.actions {
         match action {
-            ast::WhenAction::Log => actions.push(model::Action::Log),
+            ast::WhenAction::Log(span) => actions.push((model::Action::Log, span)),
             ast::WhenAction::OutputSet(action) => {
-                let output = sym_table.require_type::<SymbolOutput>(&action.target_output)?;
+                let output = sym_table
+                    .require_type::<SymbolOutput>(&action.target_output)
+                    .at(action.target_output.span)?;
                 let action_value = (match action.value {
                     ast::OutputValue::Between(lo, hi) => ProgramCheckResult::Ok(
-                        OutputValue::Between(cast_percent(lo)?, cast_percent(hi)?),
+                        OutputValue::Between(cast_percent(&lo)?, cast_percent(&hi)?),
                     ),
-                    ast::OutputValue::Fixed(value) => Ok(OutputValue::Fixed(cast_percent(value)?)),
+                    ast::OutputValue::Fixed(value) => Ok(OutputValue::Fixed(cast_percent(&value)?)),
+                    ast::OutputValue::Curve(points) => {
+                        Ok(OutputValue::Curve(cast_curve(sensor, points, action.span)?))
+                    }
                 })?;
 
-                actions.push(model::Action::OutputSet(model::OutputSetGeneric::new(
-                    output.clone(),
-                    action_value,
-                )))
+                actions.push((
+                    model::Action::OutputSet(model::OutputSetGeneric::new(
+                        output.clone(),
+                        action_value,
+                    )),
+                    action.span,
+                ))
             }
         }
     }
 
     let behavior = match rule.condition {
         ast::WhenCondition::Between(low, high) => {
-            model::WhenBehavior::Bounded(model::WhenBoundedBehavior::new(low, high, actions))
+            model::WhenBehavior::Bounded(model::WhenBoundedBehavior::new(
+                cast_sensor_value(sensor, low)?,
+                cast_sensor_value(sensor, high)?,
+                actions.into_iter().map(|(action, _)| action).collect(),
+            ))
         }
         ast::WhenCondition::GreaterThan(low) => {
             model::WhenBehavior::Unbounded(model::WhenUnboundedBehavior::new(
-                model::WhenUnboundedCond::Greater(low),
-                into_fixed_actions(actions)?,
+                model::WhenUnboundedCond::Greater(cast_sensor_value(sensor, low)?),
+                into_unbounded_actions(actions)?,
             ))
         }
         ast::WhenCondition::LessThan(high) => {
             model::WhenBehavior::Unbounded(model::WhenUnboundedBehavior::new(
-                model::WhenUnboundedCond::Less(high),
-                into_fixed_actions(actions)?,
+                model::WhenUnboundedCond::Less(cast_sensor_value(sensor, high)?),
+                into_unbounded_actions(actions)?,
             ))
         }
     };
 
-    let rule = model::When::new(rule_index, rule.tag, sensor.clone(), behavior);
+    let hysteresis = match rule.hysteresis {
+        Some(quantity) => cast_sensor_delta(sensor, quantity)?,
+        None => 0,
+    };
+
+    let rule = model::When::new(
+        rule_index,
+        rule.tag,
+        sensor.clone(),
+        behavior,
+        hysteresis,
+        rule.hold.unwrap_or_default(),
+        rule.release.unwrap_or_default(),
+        rule.span,
+    );
     Ok(rule)
 }
 
-pub fn check_program(program: ast::Program) -> ProgramCheckResult<model::ThermalProgram> {
+/// Checks the whole program, reporting all the errors found instead
+/// of stopping on the first one. A statement with errors is left out
+/// of the program, so the statements referring to it may raise errors
+/// too.
+pub fn check_program(program: ast::Program) -> Result<model::ThermalProgram, Vec<CheckError>> {
     let mut symbol_table = SymbolTable::new();
     let mut when_rules = Vec::<model::When>::new();
+    let mut failure_policy: Option<model::FailurePolicy> = None;
+    let mut stall_alarm: Option<model::StallAlarm> = None;
+    let mut errors = Vec::new();
 
     for rule in program.statements {
-        match rule {
-            ast::Rule::Define(def) => {
-                process_define_rule(&mut symbol_table, def)?;
-            }
+        let result = match rule {
+            ast::Rule::Define(def) => process_define_rule(&mut symbol_table, def),
 
             ast::Rule::When(when) => {
-                when_rules.push(process_when_rule(
-                    &mut symbol_table,
-                    when_rules.len() as u32,
-                    when,
-                )?);
+                process_when_rule(&mut symbol_table, when_rules.len() as u32, when)
+                    .map(|rule| when_rules.push(rule))
             }
+
+            ast::Rule::FailurePolicy(policy) => {
+                if failure_policy.is_some() {
+                    Err(SemanticError::DuplicateFailurePolicy.at(policy.span))
+                } else {
+                    process_failure_policy(policy).map(|policy| failure_policy = Some(policy))
+                }
+            }
+
+            ast::Rule::StallAlarm(alarm) => {
+                if stall_alarm.is_some() {
+                    Err(SemanticError::DuplicateStallAlarm.at(alarm.span))
+                } else {
+                    cast_percent(&alarm.threshold).map(|threshold| {
+                        stall_alarm = Some(model::StallAlarm::new(
+                            threshold,
+                            alarm.grace_period,
+                            alarm.actions,
+                        ))
+                    })
+                }
+            }
+        };
+
+        if let Err(err) = result {
+            errors.push(err);
         }
     }
 
-    Ok(model::ThermalProgram::new(symbol_table, when_rules))
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(model::ThermalProgram::new(
+        symbol_table,
+        when_rules,
+        failure_policy.unwrap_or_default(),
+        stall_alarm.unwrap_or_default(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{conffile::ProgramParser, ProgramCheckError};
+
+    /// Checks the given rules, along with a device with a TERMISTOR
+    /// sensor `temp`, a FAN sensor `rpm` and a PWM output `fan`.
+    fn check(rules: &str) -> Result<model::ThermalProgram, Vec<CheckError>> {
+        let source = format!(
+            "DEFINE DEVICE `dev` UDEV TAG \"dev\" DRIVER \"hwmon\";\n\
+             DEFINE SENSOR `temp` DEVICE `dev` TYPE TERMISTOR INDEX 1;\n\
+             DEFINE SENSOR `rpm` DEVICE `dev` TYPE FAN INDEX 1;\n\
+             DEFINE OUTPUT `fan` DEVICE `dev` TYPE PWM INDEX 1;\n\
+             {}",
+            rules
+        );
+        check_program(ProgramParser::new().parse(&source).unwrap())
+    }
+
+    fn accepted(rules: &str) -> model::ThermalProgram {
+        check(rules).unwrap_or_else(|errors| panic!("{}", errors[0]))
+    }
+
+    fn rejected(rules: &str) -> SemanticError {
+        match check(rules) {
+            Ok(_) => panic!("The rules were accepted"),
+            Err(mut errors) => match errors.remove(0).error {
+                ProgramCheckError::SemanticError(error) => error,
+                error => panic!("{}", error),
+            },
+        }
+    }
+
+    /// Returns the value the sensor of an unbounded rule is compared
+    /// against.
+    fn threshold(when: &model::When) -> SensorValue {
+        match &when.behavior {
+            model::WhenBehavior::Unbounded(rule) => match rule.condition {
+                model::WhenUnboundedCond::Greater(value) => value,
+                model::WhenUnboundedCond::Less(value) => value,
+            },
+            model::WhenBehavior::Bounded(_) => panic!("The rule is bounded"),
+        }
+    }
+
+    #[test]
+    fn fan_rule() {
+        let program = accepted(
+            "WHEN `rpm` < 500 RPM DO LOG; END\n\
+             WHEN `rpm` > 2000 DO SET `fan` TO 40%; END",
+        );
+        assert_eq!(
+            threshold(&program.rules[0]),
+            SensorValue::Fan(Rpm::from_rpm(500))
+        );
+        assert_eq!(
+            threshold(&program.rules[1]),
+            SensorValue::Fan(Rpm::from_rpm(2000))
+        );
+    }
+
+    #[test]
+    fn fan_rule_non_integer_rpm() {
+        assert!(matches!(
+            rejected("WHEN `rpm` < 500.5 DO LOG; END"),
+            SemanticError::NonIntegerRpm(_)
+        ));
+    }
+
+    #[test]
+    fn fan_rule_negative_rpm() {
+        assert!(matches!(
+            rejected("WHEN `rpm` < -100 DO LOG; END"),
+            SemanticError::NumberOutOfBounds(NumBoundary::GreaterOrEqual(_), _)
+        ));
+    }
+
+    #[test]
+    fn fan_rule_temperature_unit() {
+        assert!(matches!(
+            rejected("WHEN `rpm` < 50C DO LOG; END"),
+            SemanticError::IncompatibleUnit {
+                sensor_type: ast::SensorType::Fan,
+                unit: ast::Unit::Celsius,
+                ..
+            }
+        ));
+        assert!(matches!(
+            rejected("WHEN `temp` > 500 RPM DO LOG; END"),
+            SemanticError::IncompatibleUnit {
+                sensor_type: ast::SensorType::Termistor,
+                unit: ast::Unit::Rpm,
+                ..
+            }
+        ));
+    }
+
+    fn celsius_threshold(rules: &str) -> i32 {
+        match threshold(&accepted(rules).rules[0]) {
+            SensorValue::Temp(temp) => temp.mcelsius(),
+            value => panic!("Unexpected value {}", value),
+        }
+    }
+
+    #[test]
+    fn temperature_units() {
+        assert_eq!(celsius_threshold("WHEN `temp` > 28.5 DO LOG; END"), 28_500);
+        assert_eq!(celsius_threshold("WHEN `temp` > 28.5C DO LOG; END"), 28_500);
+        assert_eq!(celsius_threshold("WHEN `temp` > 85F DO LOG; END"), 29_444);
+        assert_eq!(celsius_threshold("WHEN `temp` > 300K DO LOG; END"), 26_850);
+        assert_eq!(celsius_threshold("WHEN `temp` > -40F DO LOG; END"), -40_000);
+    }
+
+    #[test]
+    fn temperature_unit_on_fan() {
+        assert!(matches!(
+            rejected("WHEN `rpm` > 85F DO LOG; END"),
+            SemanticError::IncompatibleUnit {
+                unit: ast::Unit::Fahrenheit,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn unit_letters_as_tags() {
+        let program = accepted(
+            "C: WHEN `temp` > 28C DO LOG; END\n\
+             F: WHEN `temp` > 85F DO LOG; END\n\
+             K: WHEN `temp` > 300K DO LOG; END",
+        );
+        let tags: Vec<_> = program.rules.iter().map(|rule| rule.rule_name()).collect();
+        assert_eq!(tags, ["C", "F", "K"]);
+    }
+
+    #[test]
+    fn temperatures_out_of_range() {
+        for rules in &[
+            "WHEN `temp` > -1K DO LOG; END",
+            "WHEN `temp` > -460F DO LOG; END",
+            "WHEN `temp` > -273.16C DO LOG; END",
+        ] {
+            assert!(matches!(
+                rejected(rules),
+                SemanticError::NumberOutOfBounds(NumBoundary::GreaterOrEqual(_), _)
+            ));
+        }
+
+        for rules in &[
+            "WHEN `temp` > 3000000 DO LOG; END",
+            "WHEN `temp` > 50 HYSTERESIS 3000000 DO LOG; END",
+        ] {
+            assert!(matches!(
+                rejected(rules),
+                SemanticError::NumberOutOfBounds(NumBoundary::LessOrEqual(_), _)
+            ));
+        }
+
+        assert_eq!(celsius_threshold("WHEN `temp` > 0K DO LOG; END"), -273_150);
+    }
 }
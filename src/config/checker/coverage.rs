@@ -0,0 +1,289 @@
+//! Analysis of the values of the sensors for which the rules set each
+//! output. Since the conditions of the rules are compared against the
+//! raw values of the sensors (millicelsius for temperatures), ranges
+//! that look contiguous on the configuration may leave values out,
+//! like 37.5 °C between `BETWEEN 28 AND 37` and `> 38`.
+
+use super::model::{ThermalProgram, When, WhenBehavior, WhenUnboundedCond};
+use crate::config::{
+    ast::{self, Span},
+    RuleSensor, SymbolOutput,
+};
+use crate::types::{Rpm, SensorValue, TempCelsius};
+use std::rc::Rc;
+
+/// A finding of the analysis. Unlike errors, warnings do not prevent
+/// the program from running.
+#[derive(Debug, new)]
+pub struct CoverageWarning {
+    /// Location of the rule the warning is about, if it is about a
+    /// single rule.
+    pub span: Option<Span>,
+    pub message: String,
+}
+
+/// Closed range of raw sensor values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Range {
+    lo: i64,
+    hi: i64,
+}
+
+impl Range {
+    fn intersection(self, other: Range) -> Option<Range> {
+        let range = Range {
+            lo: self.lo.max(other.lo),
+            hi: self.hi.min(other.hi),
+        };
+        if range.lo <= range.hi {
+            Some(range)
+        } else {
+            None
+        }
+    }
+}
+
+/// Values a sensor of the given type can take.
+fn domain(sensor_type: ast::SensorType) -> Range {
+    match sensor_type {
+        ast::SensorType::Termistor => Range {
+            lo: i32::MIN as i64,
+            hi: i32::MAX as i64,
+        },
+        ast::SensorType::Fan => Range {
+            lo: 0,
+            hi: i32::MAX as i64,
+        },
+    }
+}
+
+/// Values of the sensor that trigger the given rule, if any, ignoring
+/// its hysteresis.
+fn condition_range(when: &When) -> Option<Range> {
+    let domain = domain(when.sensor.sensor_type());
+    let range = match &when.behavior {
+        WhenBehavior::Bounded(rule) => Range {
+            lo: rule.cond_min_value.raw() as i64,
+            hi: rule.cond_max_value.raw() as i64,
+        },
+        WhenBehavior::Unbounded(rule) => match rule.condition {
+            WhenUnboundedCond::Greater(lo) => Range {
+                lo: lo.raw() as i64 + 1,
+                hi: domain.hi,
+            },
+            WhenUnboundedCond::Less(hi) => Range {
+                lo: domain.lo,
+                hi: hi.raw() as i64 - 1,
+            },
+        },
+    };
+
+    domain.intersection(range)
+}
+
+/// Returns the ranges of the domain not covered by any of the given
+/// ranges, sorted.
+fn find_gaps(domain: Range, ranges: &[Range]) -> Vec<Range> {
+    let mut ranges = ranges.to_vec();
+    ranges.sort_by_key(|range| range.lo);
+
+    let mut gaps = Vec::new();
+    let mut next = domain.lo;
+    for range in ranges {
+        if range.lo > next {
+            gaps.push(Range {
+                lo: next,
+                hi: range.lo - 1,
+            });
+        }
+        next = next.max(range.hi + 1);
+    }
+
+    if next <= domain.hi {
+        gaps.push(Range {
+            lo: next,
+            hi: domain.hi,
+        });
+    }
+
+    gaps
+}
+
+fn sensor_value(sensor_type: ast::SensorType, raw: i64) -> SensorValue {
+    match sensor_type {
+        ast::SensorType::Termistor => SensorValue::Temp(TempCelsius::from_mcelsius(raw as i32)),
+        ast::SensorType::Fan => SensorValue::Fan(Rpm::from_rpm(raw as u32)),
+    }
+}
+
+fn describe_range(sensor_type: ast::SensorType, range: Range) -> String {
+    let domain = domain(sensor_type);
+    let value = |raw| sensor_value(sensor_type, raw);
+
+    match (range.lo <= domain.lo, range.hi >= domain.hi) {
+        (true, true) => "at any value".to_string(),
+        (true, false) => format!("below {}", value(range.hi + 1)),
+        (false, true) => format!("above {}", value(range.lo - 1)),
+        (false, false) if range.lo == range.hi => format!("exactly {}", value(range.lo)),
+        (false, false) => format!("between {} and {}", value(range.lo), value(range.hi)),
+    }
+}
+
+/// Rules that set an output through the same sensor, along with the
+/// values of the sensor that trigger them, in order of definition.
+struct SensorCoverage<'a> {
+    sensor: &'a RuleSensor,
+    rules: Vec<(&'a When, Range)>,
+}
+
+fn analyze_output(
+    program: &ThermalProgram,
+    output: &Rc<SymbolOutput>,
+    warnings: &mut Vec<CoverageWarning>,
+) {
+    let mut coverages: Vec<SensorCoverage> = Vec::new();
+    for rule in program.rules.iter() {
+        if !rule.iter_targets().any(|target| Rc::ptr_eq(target, output)) {
+            continue;
+        }
+
+        let range = match condition_range(rule) {
+            Some(range) => range,
+            None => continue,
+        };
+
+        match coverages
+            .iter_mut()
+            .find(|coverage| coverage.sensor.name() == rule.sensor.name())
+        {
+            Some(coverage) => coverage.rules.push((rule, range)),
+            None => coverages.push(SensorCoverage {
+                sensor: &rule.sensor,
+                rules: vec![(rule, range)],
+            }),
+        }
+    }
+
+    if coverages.is_empty() {
+        warnings.push(CoverageWarning::new(
+            None,
+            format!("Output `{}` is not set by any rule.", output.name),
+        ));
+        return;
+    }
+
+    let gaps = coverages
+        .iter()
+        .map(|coverage| {
+            let ranges = coverage
+                .rules
+                .iter()
+                .map(|&(_, range)| range)
+                .collect::<Vec<_>>();
+            find_gaps(domain(coverage.sensor.sensor_type()), &ranges)
+        })
+        .collect::<Vec<_>>();
+
+    // An output covered by the rules of one of its sensors is always
+    // set, whatever the values of the rest of sensors.
+    if gaps.iter().all(|gaps| !gaps.is_empty()) {
+        for (coverage, gaps) in coverages.iter().zip(gaps) {
+            for gap in gaps {
+                warnings.push(CoverageWarning::new(
+                    None,
+                    format!(
+                        "No rule sets output `{}` when `{}` is {}, so the output keeps its previous value.",
+                        output.name,
+                        coverage.sensor.name(),
+                        describe_range(coverage.sensor.sensor_type(), gap)
+                    ),
+                ));
+            }
+        }
+    }
+
+    if let ast::OutputPriorization::Latest = output.priorization {
+        for coverage in coverages.iter() {
+            for (index, &(later, later_range)) in coverage.rules.iter().enumerate() {
+                for &(earlier, earlier_range) in coverage.rules[..index].iter() {
+                    if let Some(overlap) = earlier_range.intersection(later_range) {
+                        warnings.push(CoverageWarning::new(
+                            Some(later.span),
+                            format!(
+                                "Rules {} and {} both set output `{}` when `{}` is {}. Rule {} silently wins, as the output prioritizes the latest rule.",
+                                earlier.rule_name(),
+                                later.rule_name(),
+                                output.name,
+                                coverage.sensor.name(),
+                                describe_range(coverage.sensor.sensor_type(), overlap),
+                                later.rule_name()
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Analyzes, for each output, the values of its sensors for which
+/// some rule sets it. Warns about the values for which no rule sets
+/// the output, and about the values for which several rules set an
+/// output that prioritizes the latest rule.
+pub fn analyze_coverage(program: &ThermalProgram) -> Vec<CoverageWarning> {
+    let mut outputs = program
+        .symbol_table
+        .get_all_symbols_of_type::<SymbolOutput>();
+    outputs.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut warnings = Vec::new();
+    for output in outputs {
+        analyze_output(program, output, &mut warnings);
+    }
+
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(lo: i64, hi: i64) -> Range {
+        Range { lo, hi }
+    }
+
+    #[test]
+    fn find_gaps_between_ranges() {
+        let domain = domain(ast::SensorType::Termistor);
+        assert_eq!(
+            find_gaps(domain, &[range(38_000, domain.hi), range(28_000, 37_000)]),
+            vec![range(domain.lo, 27_999), range(37_001, 37_999)]
+        );
+    }
+
+    #[test]
+    fn find_gaps_overlapping_ranges() {
+        let domain = domain(ast::SensorType::Fan);
+        assert_eq!(
+            find_gaps(domain, &[range(0, 500), range(200, 300), range(501, 900)]),
+            vec![range(901, domain.hi)]
+        );
+    }
+
+    #[test]
+    fn describe_gaps() {
+        let domain = domain(ast::SensorType::Termistor);
+        assert_eq!(
+            describe_range(ast::SensorType::Termistor, range(domain.lo, 27_999)),
+            "below 28 °C"
+        );
+        assert_eq!(
+            describe_range(ast::SensorType::Termistor, range(37_001, 37_999)),
+            "between 37.001 °C and 37.999 °C"
+        );
+        assert_eq!(
+            describe_range(ast::SensorType::Termistor, range(80_001, domain.hi)),
+            "above 80 °C"
+        );
+    }
+}
@@ -1,16 +1,20 @@
 use std::{borrow::Cow, error::Error, fmt::Display};
 
-use crate::config::SymbolTableError;
+use crate::config::{
+    ast::{self, Span},
+    SymbolTableError,
+};
+use crate::types::{Percent, SensorValue};
 
 #[allow(dead_code)]
 pub enum NumBoundary {
-    Greater(i32),
-    GreaterOrEqual(i32),
-    LessOrEqual(i32),
-    Less(i32),
-    BetweenStartExclusive(i32, i32),
-    BetweenEndExclusive(i32, i32),
-    BetweenBothExclusive(i32, i32),
+    Greater(f64),
+    GreaterOrEqual(f64),
+    LessOrEqual(f64),
+    Less(f64),
+    BetweenStartExclusive(f64, f64),
+    BetweenEndExclusive(f64, f64),
+    BetweenBothExclusive(f64, f64),
 }
 
 impl NumBoundary {
@@ -33,8 +37,33 @@ impl NumBoundary {
 
 pub enum SemanticError {
     BetweenActionInUnboundedRule,
-    NumberOutOfBounds(NumBoundary, i32),
+    DuplicateFailurePolicy,
+    DuplicateStallAlarm,
+    TachometerNotFan(String),
+    NumberOutOfBounds(NumBoundary, f64),
+    NonIntegerRpm(f64),
     InvalidPercent(i32),
+    CurveTooShort(usize),
+    NonMonotonicCurve(SensorValue, SensorValue),
+
+    /// A limit of an output is greater than another limit it must not
+    /// exceed, given by their keywords and values.
+    ConflictingLimits(&'static str, Percent, &'static str, Percent),
+    EmptyAggregate,
+    MixedAggregate {
+        sensor: String,
+        sensor_type: ast::SensorType,
+        expected: ast::SensorType,
+    },
+    WeightOutsideAvg,
+
+    /// A sensor selected by name is not a temperature sensor.
+    NamedNonTemperature(String),
+    IncompatibleUnit {
+        sensor: String,
+        sensor_type: ast::SensorType,
+        unit: ast::Unit,
+    },
 }
 
 impl SemanticError {
@@ -43,18 +72,84 @@ impl SemanticError {
             SemanticError::BetweenActionInUnboundedRule => {
                 "Use of BETWEEN operator in action inside an rule without a BETWEEN trigger.".into()
             }
+            SemanticError::DuplicateFailurePolicy => {
+                "The global failure policy has been defined more than once.".into()
+            }
+            SemanticError::DuplicateStallAlarm => {
+                "The stall alarm has been defined more than once.".into()
+            }
+            SemanticError::TachometerNotFan(sensor) => format!(
+                "The tachometer of an output must be a sensor of type FAN, but `{}` is not.",
+                sensor
+            )
+            .into(),
             SemanticError::NumberOutOfBounds(boundary, got) => {
                 format!("Expected a number {}, but {} got.", boundary.as_str(), got).into()
             }
+            SemanticError::NonIntegerRpm(got) => format!(
+                "Fan speeds must be a whole number of RPM, but {} got.",
+                got
+            )
+            .into(),
             SemanticError::InvalidPercent(got) => format!(
                 "Invalid percent value. Expected a value between 0% and 100%, but {}% got.",
                 got
             )
             .into(),
+            SemanticError::CurveTooShort(got) => format!(
+                "A curve requires at least 2 points, but {} got.",
+                got
+            )
+            .into(),
+            SemanticError::NonMonotonicCurve(prev, next) => format!(
+                "The values of the points of a curve must be strictly increasing, but {} comes after {}.",
+                next, prev
+            )
+            .into(),
+            SemanticError::ConflictingLimits(limit, value, other, other_value) => format!(
+                "{} {} cannot be greater than {} {}.",
+                limit, value, other, other_value
+            )
+            .into(),
+            SemanticError::EmptyAggregate => {
+                "An aggregate sensor requires at least one sensor.".into()
+            }
+            SemanticError::MixedAggregate {
+                sensor,
+                sensor_type,
+                expected,
+            } => format!(
+                "All the sensors of an aggregate must be of the same type, but `{}` is of type {} instead of {}.",
+                sensor, sensor_type, expected
+            )
+            .into(),
+            SemanticError::WeightOutsideAvg => {
+                "Weights can only be given to the sensors of an AVG aggregate.".into()
+            }
+            SemanticError::NamedNonTemperature(sensor) => format!(
+                "Only sensors of type TERMISTOR can be selected by name, but `{}` is not.",
+                sensor
+            )
+            .into(),
+            SemanticError::IncompatibleUnit {
+                sensor,
+                sensor_type,
+                unit,
+            } => format!(
+                "Cannot compare sensor `{}` of type {} against a value in {}.",
+                sensor, sensor_type, unit
+            )
+            .into(),
         }
     }
 }
 
+impl SemanticError {
+    pub fn at(self, span: Span) -> CheckError {
+        ProgramCheckError::SemanticError(self).at(span)
+    }
+}
+
 impl Display for SemanticError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Semantic Error: {}", self.as_str())
@@ -73,7 +168,20 @@ impl From<SymbolTableError> for ProgramCheckError {
     }
 }
 
-pub type ProgramCheckResult<T> = Result<T, ProgramCheckError>;
+impl ProgramCheckError {
+    pub fn at(self, span: Span) -> CheckError {
+        CheckError::new(self, span)
+    }
+
+    /// Description of the error, without the kind of error.
+    pub fn message(&self) -> Cow<str> {
+        match self {
+            ProgramCheckError::SymbolTableError(error) => error.to_string().into(),
+            ProgramCheckError::Other(error) => error.to_string().into(),
+            ProgramCheckError::SemanticError(error) => error.as_str(),
+        }
+    }
+}
 
 impl Display for ProgramCheckError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -84,3 +192,31 @@ impl Display for ProgramCheckError {
         }
     }
 }
+
+/// An error found while checking a program, along with the location of
+/// the source that caused it.
+#[derive(new)]
+pub struct CheckError {
+    pub error: ProgramCheckError,
+    pub span: Span,
+}
+
+impl Display for CheckError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+pub type ProgramCheckResult<T> = Result<T, CheckError>;
+
+/// Attaches a location of the source to the errors of the symbol
+/// table.
+pub trait AtSpan<T> {
+    fn at(self, span: Span) -> ProgramCheckResult<T>;
+}
+
+impl<T> AtSpan<T> for Result<T, SymbolTableError> {
+    fn at(self, span: Span) -> ProgramCheckResult<T> {
+        self.map_err(|err| ProgramCheckError::from(err).at(span))
+    }
+}
@@ -1,6 +1,8 @@
 mod checker;
+mod coverage;
 mod error;
 pub mod model;
 
 pub use checker::*;
+pub use coverage::*;
 pub use error::*;
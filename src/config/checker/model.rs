@@ -1,26 +1,181 @@
+use crate::config::ast::Span;
+use crate::config::RuleSensor;
 use crate::config::SymbolOutput;
 use crate::config::SymbolTable;
-use crate::{config::SymbolSensor, types::Percent};
-use std::{borrow::Cow, rc::Rc};
+use crate::config::{ast, SymbolDevice};
+use crate::types::{Percent, SensorValue};
+use std::{borrow::Cow, convert::TryFrom, rc::Rc, time::Duration};
 
 #[derive(new, Debug)]
 pub struct ThermalProgram {
     pub symbol_table: SymbolTable,
     pub rules: Vec<When>,
+    pub failure_policy: FailurePolicy,
+    pub stall_alarm: StallAlarm,
+}
+
+impl ThermalProgram {
+    /// Returns the failure policy that applies to the outputs of the
+    /// given device: the one defined on the device itself, if any, or
+    /// the global one otherwise.
+    pub fn failure_policy_for<'a>(&'a self, device: &'a SymbolDevice) -> &'a FailurePolicy {
+        device
+            .failure_policy
+            .as_ref()
+            .unwrap_or(&self.failure_policy)
+    }
+}
+
+/// Defines when an output with a tachometer is considered stalled,
+/// and what to do when that happens.
+#[derive(Debug, new)]
+pub struct StallAlarm {
+    /// An output is considered stalled if it is commanded above this
+    /// value, but its tachometer reads 0 RPM.
+    pub threshold: Percent,
+
+    /// Time an output must be stalled before raising the alarm.
+    pub grace_period: Duration,
+    pub actions: Vec<ast::AlarmAction>,
+}
+
+impl Default for StallAlarm {
+    fn default() -> Self {
+        StallAlarm::new(
+            Percent::try_from(20).unwrap(),
+            Duration::from_secs(5),
+            Vec::new(),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default, new)]
+pub struct FailurePolicy {
+    pub action: ast::FailureAction,
+
+    /// Number of consecutive failed evaluations of an output that are
+    /// tolerated before applying the action.
+    pub retries: u32,
+}
+
+/// Limits on the values written to an output, applied to the value
+/// the triggered rules set it to, once combined.
+#[derive(Debug, Clone, Default, new)]
+pub struct OutputLimits {
+    pub min: Option<Percent>,
+    pub max: Option<Percent>,
+
+    /// Values below this one stop the output, instead of being raised
+    /// to the minimum.
+    pub stop_below: Option<Percent>,
+
+    /// Value the output is kicked to when starting from standstill,
+    /// and for how long.
+    pub spin_up: Option<(Percent, Duration)>,
+}
+
+impl OutputLimits {
+    /// Lowest value other than 0% the output may be set to, if any.
+    pub fn lowest_running(&self) -> Option<Percent> {
+        self.min.or(self.stop_below)
+    }
+
+    /// Applies the limits to the given value. If the value changes,
+    /// the limit that changed it is returned too.
+    pub fn apply(&self, value: Percent) -> (Percent, Option<&'static str>) {
+        if let Some(max) = self.max {
+            if value > max {
+                return (max, Some("MAX"));
+            }
+        }
+
+        if let Some(stop_below) = self.stop_below {
+            if value < stop_below {
+                let stopped = Percent::try_from(0).unwrap();
+                return (stopped, (value != stopped).then(|| "STOP BELOW"));
+            }
+        }
+
+        if let Some(min) = self.min {
+            if value < min {
+                return (min, Some("MIN"));
+            }
+        }
+
+        (value, None)
+    }
 }
 
 #[derive(Debug)]
 pub enum OutputValue {
     Between(Percent, Percent),
     Fixed(Percent),
+    Curve(Curve),
+}
+
+/// Output values that doesn't depend on the range of the condition
+/// of the rule, and therefore can be used on any rule.
+#[derive(Debug)]
+pub enum UnboundedOutputValue {
+    Fixed(Percent),
+    Curve(Curve),
+}
+
+/// Piecewise-linear curve that maps sensor values to percents. The
+/// points of the curve are sorted by strictly increasing sensor
+/// value, and all of them are of the same kind.
+#[derive(Debug)]
+pub struct Curve {
+    points: Vec<(SensorValue, Percent)>,
+}
+
+impl Curve {
+    /// Creates a new curve from its points. The points must be sorted
+    /// by strictly increasing sensor value, and there must be at least
+    /// two of them.
+    pub fn new(points: Vec<(SensorValue, Percent)>) -> Curve {
+        assert!(points.len() >= 2);
+        Curve { points }
+    }
+
+    /// Computes the value of the curve for the given sensor value, by
+    /// linearly interpolating its two nearest points. Values out of
+    /// the range of the curve takes the value of the nearest end.
+    pub fn value_at(&self, value: SensorValue) -> Percent {
+        let raw = value.raw();
+        let (first_point, first_value) = self.points[0];
+        let (last_point, last_value) = self.points[self.points.len() - 1];
+
+        if raw <= first_point.raw() {
+            return first_value;
+        }
+
+        if raw >= last_point.raw() {
+            return last_value;
+        }
+
+        let upper = self
+            .points
+            .iter()
+            .position(|&(point, _)| point.raw() > raw)
+            .unwrap();
+        let (lo_point, lo_value) = self.points[upper - 1];
+        let (hi_point, hi_value) = self.points[upper];
+
+        let progress = (raw - lo_point.raw()) as f64 / (hi_point.raw() - lo_point.raw()) as f64;
+        let lo_value = lo_value.value() as f64;
+        let hi_value = hi_value.value() as f64;
+
+        Percent::try_from((lo_value + progress * (hi_value - lo_value)).round() as i32).unwrap()
+    }
 }
 
 #[derive(Debug, new)]
-pub struct OutputSetFixed {
+pub struct OutputSetUnbounded {
     pub target: Rc<SymbolOutput>,
 
     // TODO May accept other value types in the future (ej. absolute values), but let's keep it simple for now.
-    pub value: Percent,
+    pub value: UnboundedOutputValue,
 }
 
 #[derive(Debug, new)]
@@ -48,20 +203,42 @@ pub enum AnyAction<'a> {
         target: &'a Rc<SymbolOutput>,
         value: Percent,
     },
+    CurveOutputSet {
+        target: &'a Rc<SymbolOutput>,
+        curve: &'a Curve,
+    },
 }
 
 #[derive(Debug)]
 pub enum WhenUnboundedCond {
-    Greater(i32),
-    Less(i32),
+    Greater(SensorValue),
+    Less(SensorValue),
 }
 
 #[derive(Debug, new)]
 pub struct When {
     pub rule_index: u32,
     pub tag: Option<String>,
-    pub sensor: Rc<SymbolSensor>,
+    pub sensor: RuleSensor,
     pub behavior: WhenBehavior,
+
+    /// Amount the sensor must leave the range of the condition, once
+    /// the rule is triggered, for the rule to stop being triggered. It
+    /// is expressed in the base unit of the sensor (see
+    /// `SensorValue::raw`), and is zero if no hysteresis has been
+    /// configured.
+    pub hysteresis: i32,
+
+    /// Time the condition must hold continuously before the rule is
+    /// triggered. Zero if the rule triggers immediately.
+    pub hold: Duration,
+
+    /// Time the rule is kept triggered after its condition stops
+    /// holding. Zero if the rule is released immediately.
+    pub release: Duration,
+
+    /// Location of the rule on the source of the program.
+    pub span: Span,
 }
 
 impl When {
@@ -72,6 +249,17 @@ impl When {
         }
     }
 
+    /// Returns an iterator over the outputs that may be modified by
+    /// the actions of this rule.
+    pub fn iter_targets<'a>(&'a self) -> impl Iterator<Item = &'a Rc<SymbolOutput>> {
+        self.iter_actions().filter_map(|action| match action {
+            AnyAction::Log => None,
+            AnyAction::BoundedOutputSet { target, .. } => Some(target),
+            AnyAction::FixedOutputSet { target, .. } => Some(target),
+            AnyAction::CurveOutputSet { target, .. } => Some(target),
+        })
+    }
+
     pub fn rule_name<'a>(&'a self) -> Cow<'a, str> {
         if let Some(tag) = &self.tag {
             Cow::Borrowed(tag)
@@ -107,6 +295,10 @@ impl<'a> WhenRuleIter<'a> {
                 target,
                 value: *value,
             }),
+            Some(Action::OutputSet(OutputSetGeneric {
+                target,
+                value: OutputValue::Curve(curve),
+            })) => Some(AnyAction::CurveOutputSet { target, curve }),
             None => None,
         };
 
@@ -120,12 +312,17 @@ impl<'a> WhenRuleIter<'a> {
 
         let fun = move || match iterator.next() {
             Some(Action::Log) => Some(AnyAction::Log),
-            Some(Action::OutputSet(OutputSetFixed { target, value })) => {
-                Some(AnyAction::FixedOutputSet {
-                    target,
-                    value: *value,
-                })
-            }
+            Some(Action::OutputSet(OutputSetUnbounded {
+                target,
+                value: UnboundedOutputValue::Fixed(value),
+            })) => Some(AnyAction::FixedOutputSet {
+                target,
+                value: *value,
+            }),
+            Some(Action::OutputSet(OutputSetUnbounded {
+                target,
+                value: UnboundedOutputValue::Curve(curve),
+            })) => Some(AnyAction::CurveOutputSet { target, curve }),
             None => None,
         };
 
@@ -152,12 +349,79 @@ pub enum WhenBehavior {
 #[derive(Debug, new)]
 pub struct WhenUnboundedBehavior {
     pub condition: WhenUnboundedCond,
-    pub actions: Vec<Action<OutputSetFixed>>,
+    pub actions: Vec<Action<OutputSetUnbounded>>,
 }
 
 #[derive(Debug, new)]
 pub struct WhenBoundedBehavior {
-    pub cond_min_value: i32,
-    pub cond_max_value: i32,
+    pub cond_min_value: SensorValue,
+    pub cond_max_value: SensorValue,
     pub actions: Vec<Action<OutputSetGeneric>>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::TempCelsius;
+
+    fn percent(value: i32) -> Percent {
+        Percent::try_from(value).unwrap()
+    }
+
+    fn mcelsius(value: i32) -> SensorValue {
+        SensorValue::Temp(TempCelsius::from_mcelsius(value))
+    }
+
+    fn celsius(value: i32) -> SensorValue {
+        SensorValue::Temp(TempCelsius::from_celsius(value))
+    }
+
+    fn sample_curve() -> Curve {
+        Curve::new(vec![
+            (celsius(30), percent(20)),
+            (celsius(50), percent(40)),
+            (celsius(75), percent(100)),
+        ])
+    }
+
+    #[test]
+    fn curve_value_at_points() {
+        let curve = sample_curve();
+        assert_eq!(percent(20), curve.value_at(celsius(30)));
+        assert_eq!(percent(40), curve.value_at(celsius(50)));
+        assert_eq!(percent(100), curve.value_at(celsius(75)));
+    }
+
+    #[test]
+    fn curve_value_between_points() {
+        let curve = sample_curve();
+        assert_eq!(percent(30), curve.value_at(celsius(40)));
+        assert_eq!(percent(41), curve.value_at(mcelsius(50_500)));
+        assert_eq!(percent(70), curve.value_at(mcelsius(62_500)));
+    }
+
+    #[test]
+    fn curve_value_out_of_range() {
+        let curve = sample_curve();
+        assert_eq!(percent(20), curve.value_at(celsius(-10)));
+        assert_eq!(percent(100), curve.value_at(celsius(110)));
+    }
+
+    #[test]
+    fn output_limits() {
+        let limits = OutputLimits::new(
+            Some(percent(20)),
+            Some(percent(90)),
+            Some(percent(15)),
+            None,
+        );
+        assert_eq!((percent(90), Some("MAX")), limits.apply(percent(95)));
+        assert_eq!((percent(50), None), limits.apply(percent(50)));
+        assert_eq!((percent(20), Some("MIN")), limits.apply(percent(17)));
+        assert_eq!((percent(0), Some("STOP BELOW")), limits.apply(percent(10)));
+        assert_eq!((percent(0), None), limits.apply(percent(0)));
+
+        let limits = OutputLimits::new(Some(percent(20)), None, None, None);
+        assert_eq!((percent(20), Some("MIN")), limits.apply(percent(0)));
+    }
+}
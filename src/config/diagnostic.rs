@@ -0,0 +1,209 @@
+//! Reporting of the errors found on a configuration file, pointing at
+//! the offending source like:
+//!
+//! ```text
+//! error: Undefined reference to cpu_tmp
+//!  --> /etc/fanctrl.conf:12:6
+//!    |
+//! 12 | WHEN `cpu_tmp` > 50 DO
+//!    |      ^^^^^^^^^
+//! ```
+
+use super::ast::{Span, SyntaxError};
+use super::checker::{CheckError, CoverageWarning};
+use lalrpop_util::ParseError;
+use std::{
+    error::Error,
+    fmt::{Debug, Display, Write},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A configuration file, for locating spans in it.
+#[derive(new)]
+pub struct SourceFile<'a> {
+    pub name: &'a str,
+    pub source: &'a str,
+}
+
+impl<'a> SourceFile<'a> {
+    /// Returns the line and column, both starting at 1, of the given
+    /// byte offset, along with the line it is in.
+    pub fn locate(&self, offset: usize) -> (usize, usize, &'a str) {
+        let mut offset = offset.min(self.source.len());
+        while !self.source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+
+        let before = &self.source[..offset];
+        let line_start = before.rfind('\n').map(|pos| pos + 1).unwrap_or(0);
+        let line_end = self.source[offset..]
+            .find('\n')
+            .map(|pos| offset + pos)
+            .unwrap_or_else(|| self.source.len());
+
+        let line = before.matches('\n').count() + 1;
+        let column = self.source[line_start..offset].chars().count() + 1;
+        (
+            line,
+            column,
+            self.source[line_start..line_end].trim_end_matches('\r'),
+        )
+    }
+
+    /// Renders a message about the given span, followed by the line
+    /// of the source it starts on, with the span underlined.
+    pub fn render(&self, severity: Severity, span: Span, message: &str) -> String {
+        let (line, column, text) = self.locate(span.start);
+        let gutter = " ".repeat(line.to_string().len());
+
+        // The padding replicates the tabs of the line, so the carets
+        // stay aligned regardless of the width of a tab.
+        let padding: String = text
+            .chars()
+            .take(column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = text
+            .chars()
+            .skip(column - 1)
+            .take(
+                self.source
+                    .get(span.start..span.end)
+                    .map(|spanned| spanned.chars().count())
+                    .unwrap_or(0),
+            )
+            .count()
+            .max(1);
+
+        let mut result = String::new();
+        let _ = writeln!(result, "{}: {}", severity, message);
+        let _ = writeln!(result, "{}--> {}:{}:{}", gutter, self.name, line, column);
+        let _ = writeln!(result, "{} |", gutter);
+        let _ = writeln!(result, "{} | {}", line, text);
+        let _ = write!(result, "{} | {}{}", gutter, padding, "^".repeat(width));
+        result
+    }
+
+    pub fn render_parse_error<T: Display>(
+        &self,
+        error: &ParseError<usize, T, SyntaxError>,
+    ) -> String {
+        let (span, message) = match error {
+            ParseError::InvalidToken { location } => (
+                Span::new(*location, *location + 1),
+                "Invalid token".to_string(),
+            ),
+            ParseError::UnrecognizedEOF { location, expected } => (
+                Span::new(*location, *location),
+                format!("Unexpected end of file{}", describe_expected(expected)),
+            ),
+            ParseError::UnrecognizedToken {
+                token: (start, token, end),
+                expected,
+            } => (
+                Span::new(*start, *end),
+                format!("Unexpected `{}`{}", token, describe_expected(expected)),
+            ),
+            ParseError::ExtraToken {
+                token: (start, token, end),
+            } => (Span::new(*start, *end), format!("Unexpected `{}`", token)),
+            ParseError::User { error } => (error.span, error.message.clone()),
+        };
+
+        self.render(Severity::Error, span, &message)
+    }
+
+    pub fn render_check_error(&self, error: &CheckError) -> String {
+        self.render(Severity::Error, error.span, &error.error.message())
+    }
+
+    pub fn render_warning(&self, warning: &CoverageWarning) -> String {
+        match warning.span {
+            Some(span) => self.render(Severity::Warning, span, &warning.message),
+            None => format!("{}: {}", Severity::Warning, warning.message),
+        }
+    }
+}
+
+fn describe_expected(expected: &[String]) -> String {
+    match expected {
+        [] => String::new(),
+        [one] => format!(", expected {}", one),
+        _ => format!(", expected one of {}", expected.join(", ")),
+    }
+}
+
+/// Errors found on a configuration file, already rendered for
+/// displaying them to the user.
+pub struct ConfigError {
+    pub count: usize,
+    pub rendered: String,
+}
+
+impl ConfigError {
+    pub fn new(diagnostics: Vec<String>) -> ConfigError {
+        ConfigError {
+            count: diagnostics.len(),
+            rendered: diagnostics.join("\n\n"),
+        }
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.rendered)?;
+        if self.count > 1 {
+            write!(f, "\n\n{} errors found on the configuration.", self.count)?;
+        }
+        Ok(())
+    }
+}
+
+// Printed as is when returned from main.
+impl Debug for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let source = "DEFINE SENSOR `a` DEVICE `dev` TYPE FAN INDEX 1;\n\tWHEN `b` > 50 DO\nEND\n";
+        let file = SourceFile::new("test.conf", source);
+        let start = source.find("`b`").unwrap();
+
+        assert_eq!(file.locate(start), (2, 7, "\tWHEN `b` > 50 DO"));
+        assert_eq!(
+            file.render(
+                Severity::Error,
+                Span::new(start, start + 3),
+                "Undefined reference to b"
+            ),
+            "error: Undefined reference to b\n \
+             --> test.conf:2:7\n  \
+             |\n\
+             2 | \tWHEN `b` > 50 DO\n  \
+             | \t     ^^^"
+        );
+    }
+}
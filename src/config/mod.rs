@@ -1,7 +1,39 @@
 pub mod ast;
 pub mod checker;
+mod diagnostic;
 mod symboltable;
 lalrpop_mod!(pub conffile, "/config/conffile.rs");
 
 pub use checker::*;
+pub use diagnostic::*;
 pub use symboltable::*;
+
+/// Parses and checks the source of a configuration file, rendering
+/// the errors found against the source. On success, the warnings
+/// found by the analysis of the program are returned along with it,
+/// already rendered.
+pub fn compile(
+    file_name: &str,
+    source: &str,
+) -> Result<(model::ThermalProgram, Vec<String>), ConfigError> {
+    let file = SourceFile::new(file_name, source);
+
+    let program = conffile::ProgramParser::new()
+        .parse(source)
+        .map_err(|err| ConfigError::new(vec![file.render_parse_error(&err)]))?;
+
+    let program = check_program(program).map_err(|errors| {
+        ConfigError::new(
+            errors
+                .iter()
+                .map(|err| file.render_check_error(err))
+                .collect(),
+        )
+    })?;
+
+    let warnings = analyze_coverage(&program)
+        .iter()
+        .map(|warning| file.render_warning(warning))
+        .collect();
+    Ok((program, warnings))
+}
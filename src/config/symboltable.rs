@@ -5,6 +5,8 @@ use std::{
 };
 
 use super::ast;
+use super::model::{FailurePolicy, OutputLimits};
+use crate::types::SensorValue;
 
 pub enum SymbolTableError {
     Clash(String),
@@ -47,6 +49,7 @@ pub trait SymbolType {
 pub enum Symbol {
     Device(Rc<SymbolDevice>),
     Sensor(Rc<SymbolSensor>),
+    Aggregate(Rc<SymbolAggregate>),
     Output(Rc<SymbolOutput>),
 }
 
@@ -55,6 +58,7 @@ impl Symbol {
         match self {
             &Symbol::Device(_) => "device",
             &Symbol::Sensor(_) => "sensor",
+            &Symbol::Aggregate(_) => "aggregate sensor",
             &Symbol::Output(_) => "output",
         }
     }
@@ -66,16 +70,121 @@ pub struct SymbolDevice {
     pub tag: String,
     pub driver: String,
     pub allow_hotplug: bool,
+    pub failure_policy: Option<FailurePolicy>,
 }
 
 #[derive(new, Debug)]
 pub struct SymbolSensor {
     pub name: String,
     pub sensor_type: ast::SensorType,
-    pub index: i32,
+    pub selector: SensorSelector,
     pub device: Rc<SymbolDevice>,
 }
 
+/// How a sensor is selected among the ones of its device. Sensors
+/// selected by name are resolved to an index when their device goes
+/// online.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SensorSelector {
+    Index(u8),
+
+    /// The type of a thermal zone.
+    Zone(String),
+
+    /// The label of a temperature, as in the `tempN_label` attributes.
+    Label(String),
+}
+
+impl Display for SensorSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SensorSelector::Index(index) => write!(f, "index {}", index),
+            SensorSelector::Zone(zone) => write!(f, "zone \"{}\"", zone),
+            SensorSelector::Label(label) => write!(f, "label \"{}\"", label),
+        }
+    }
+}
+
+/// Virtual sensor whose value is computed from the values of other
+/// sensors, all of them of the same type.
+#[derive(new, Debug)]
+pub struct SymbolAggregate {
+    pub name: String,
+    pub sensor_type: ast::SensorType,
+    pub function: ast::AggregateFunction,
+
+    /// Sensors the value is computed from, along with their weights.
+    /// Weights other than 1 are only used by averages.
+    pub inputs: Vec<(Rc<SymbolSensor>, u32)>,
+}
+
+impl SymbolAggregate {
+    /// Computes the value of the aggregate from the values of its
+    /// inputs, given in the same order.
+    pub fn compute(&self, values: &[SensorValue]) -> SensorValue {
+        let first = values[0];
+        match self.function {
+            ast::AggregateFunction::Min => {
+                values
+                    .iter()
+                    .fold(first, |min, &value| if value < min { value } else { min })
+            }
+            ast::AggregateFunction::Max => {
+                values
+                    .iter()
+                    .fold(first, |max, &value| if value > max { value } else { max })
+            }
+            ast::AggregateFunction::Avg => {
+                let (sum, weights) = values.iter().zip(self.inputs.iter()).fold(
+                    (0i64, 0i64),
+                    |(sum, weights), (value, &(_, weight))| {
+                        (
+                            sum + value.raw() as i64 * weight as i64,
+                            weights + weight as i64,
+                        )
+                    },
+                );
+                let average = (sum as f64 / weights as f64).round() as i32;
+                first.offset(average - first.raw())
+            }
+        }
+    }
+}
+
+/// Sensor a rule is evaluated against: either a sensor of a device or
+/// an aggregate of them.
+#[derive(Debug, Clone)]
+pub enum RuleSensor {
+    Sensor(Rc<SymbolSensor>),
+    Aggregate(Rc<SymbolAggregate>),
+}
+
+impl RuleSensor {
+    pub fn name(&self) -> &str {
+        match self {
+            RuleSensor::Sensor(sensor) => &sensor.name,
+            RuleSensor::Aggregate(aggregate) => &aggregate.name,
+        }
+    }
+
+    pub fn sensor_type(&self) -> ast::SensorType {
+        match self {
+            RuleSensor::Sensor(sensor) => sensor.sensor_type,
+            RuleSensor::Aggregate(aggregate) => aggregate.sensor_type,
+        }
+    }
+
+    /// Returns the sensors of devices the value is read from.
+    pub fn sources(&self) -> Vec<&Rc<SymbolSensor>> {
+        match self {
+            RuleSensor::Sensor(sensor) => vec![sensor],
+            RuleSensor::Aggregate(aggregate) => {
+                aggregate.inputs.iter().map(|(sensor, _)| sensor).collect()
+            }
+        }
+    }
+}
+
 #[derive(new, Debug)]
 pub struct SymbolOutput {
     pub name: String,
@@ -83,6 +192,21 @@ pub struct SymbolOutput {
     pub output_type: ast::OutputType,
     pub index: u8,
     pub priorization: ast::OutputPriorization,
+    pub tachometer: Option<Rc<SymbolSensor>>,
+
+    /// Maximum increase and decrease of the value of the output per
+    /// second, in percentage points, if limited.
+    pub ramp_up: Option<u32>,
+    pub ramp_down: Option<u32>,
+    pub limits: OutputLimits,
+}
+
+impl SymbolDevice {
+    /// Returns whether both symbols bind the same device, through the
+    /// same driver and under the same name.
+    pub fn same_binding(&self, other: &SymbolDevice) -> bool {
+        self.name == other.name && self.tag == other.tag && self.driver == other.driver
+    }
 }
 
 impl SymbolType for SymbolDevice {
@@ -115,6 +239,21 @@ impl SymbolType for SymbolSensor {
     }
 }
 
+impl SymbolType for SymbolAggregate {
+    type Value = SymbolAggregate;
+
+    fn name() -> &'static str {
+        "aggregate sensor"
+    }
+
+    fn match_entry<'a>(s: &Symbol) -> Option<&Rc<Self::Value>> {
+        match s {
+            Symbol::Aggregate(a) => Some(a),
+            _ => None,
+        }
+    }
+}
+
 impl SymbolType for SymbolOutput {
     type Value = SymbolOutput;
 
@@ -168,6 +307,21 @@ impl SymbolTable {
         )
     }
 
+    /// Looks up a sensor that rules can be evaluated against, either
+    /// of a device or an aggregate.
+    pub fn require_rule_sensor(&self, name: &str) -> SymbolTableResult<RuleSensor> {
+        match self.map.get(name) {
+            Some(Symbol::Sensor(sensor)) => Ok(RuleSensor::Sensor(sensor.clone())),
+            Some(Symbol::Aggregate(aggregate)) => Ok(RuleSensor::Aggregate(aggregate.clone())),
+            Some(symbol) => Err(SymbolTableError::UnexpectedType {
+                name: name.to_string(),
+                expected: SymbolSensor::name().into(),
+                found: symbol.name().into(),
+            }),
+            None => Err(SymbolTableError::NotFound(name.to_string())),
+        }
+    }
+
     pub fn get_all_symbols_of_type<A: SymbolType>(&self) -> Vec<&Rc<A::Value>> {
         self.map
             .iter()
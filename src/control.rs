@@ -0,0 +1,336 @@
+//! Control socket of the daemon. Clients connect to a Unix domain
+//! socket and send requests as JSON objects, one per line. The
+//! daemon answers each request with a single line containing a JSON
+//! object.
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+};
+
+pub const DEFAULT_SOCKET_PATH: &str = "/run/fancontrol.sock";
+
+/// Maximum length of a single request. Clients sending longer lines
+/// are disconnected.
+const MAX_REQUEST_LEN: usize = 64 * 1024;
+
+/// Maximum length of the responses waiting to be written to a single
+/// client. Clients that don't read their responses are disconnected
+/// once it is exceeded.
+const MAX_PENDING_RESPONSE_LEN: usize = 256 * 1024;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    /// Reads the current value of all the sensors of the online
+    /// devices.
+    GetSensors,
+
+    /// Returns the rules that were triggered on the last evaluation,
+    /// and the values written to each output.
+    GetRules,
+
+    /// Sets an output to a fixed value for the given amount of
+    /// seconds, ignoring the configured rules.
+    Override {
+        output: String,
+        value: i32,
+        seconds: u64,
+    },
+
+    /// Clears the override of an output, or all of them if no output
+    /// is given.
+    ClearOverrides {
+        #[serde(default)]
+        output: Option<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Response {
+    Ok { data: serde_json::Value },
+    Error { message: String },
+}
+
+impl Response {
+    pub fn ok<T: Serialize>(data: T) -> Response {
+        match serde_json::to_value(data) {
+            Ok(data) => Response::Ok { data },
+            Err(err) => Response::error(err),
+        }
+    }
+
+    pub fn error<E: ToString>(message: E) -> Response {
+        Response::Error {
+            message: message.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, new)]
+pub struct SensorReading {
+    pub sensor: String,
+    pub device: String,
+    pub value: Option<f64>,
+    pub unit: Option<&'static str>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct RulesStatus {
+    pub triggered: Vec<String>,
+    pub outputs: Vec<OutputStatus>,
+}
+
+#[derive(Serialize, Debug, Clone, new)]
+pub struct OutputStatus {
+    pub output: String,
+    pub value: u8,
+
+    /// The rule that won the output, if the value comes from a rule.
+    pub rule: Option<String>,
+    pub overridden: bool,
+}
+
+struct ControlClient {
+    stream: UnixStream,
+    buffer: Vec<u8>,
+
+    /// Responses not written yet, because the socket would block.
+    pending: Vec<u8>,
+
+    /// Whether the client has finished sending requests.
+    eof: bool,
+    closed: bool,
+}
+
+impl ControlClient {
+    fn new(stream: UnixStream) -> ControlClient {
+        ControlClient {
+            stream,
+            buffer: Vec::new(),
+            pending: Vec::new(),
+            eof: false,
+            closed: false,
+        }
+    }
+
+    /// Whether the client can be dropped: either it is disconnected,
+    /// or it won't send more requests and all its responses have been
+    /// written.
+    fn is_done(&self) -> bool {
+        self.closed || (self.eof && self.pending.is_empty())
+    }
+
+    /// Reads all the data available without blocking, and returns the
+    /// complete lines received so far.
+    fn read_lines(&mut self) -> Vec<String> {
+        if self.eof {
+            return Vec::new();
+        }
+
+        let mut chunk = [0u8; 4096];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    self.eof = true;
+                    break;
+                }
+                Ok(len) => self.buffer.extend_from_slice(&chunk[..len]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    debug!("Control client disconnected: {}", err);
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+
+        let mut lines = Vec::new();
+        while let Some(pos) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            lines.push(String::from_utf8_lossy(&line).trim().to_string());
+        }
+
+        if self.buffer.len() > MAX_REQUEST_LEN {
+            warn!("Control client sent a request too long. Disconnecting it.");
+            self.closed = true;
+        }
+
+        lines
+    }
+
+    /// Queues a response for the client, and writes as much of it as
+    /// possible without blocking. The rest is written on the next
+    /// polls of the server.
+    fn send(&mut self, response: &Response) {
+        match serde_json::to_vec(response) {
+            Ok(line) => {
+                self.pending.extend_from_slice(&line);
+                self.pending.push(b'\n');
+            }
+            Err(err) => {
+                debug!("Cannot serialize response to control client: {}", err);
+                self.closed = true;
+                return;
+            }
+        }
+
+        if self.pending.len() > MAX_PENDING_RESPONSE_LEN {
+            warn!("Control client is not reading its responses. Disconnecting it.");
+            self.closed = true;
+            return;
+        }
+
+        self.flush();
+    }
+
+    /// Writes the pending responses until the socket would block.
+    fn flush(&mut self) {
+        while !self.pending.is_empty() {
+            match self.stream.write(&self.pending) {
+                Ok(0) => {
+                    self.closed = true;
+                    break;
+                }
+                Ok(len) => {
+                    self.pending.drain(..len);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    debug!("Cannot write response to control client: {}", err);
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Server side of the control socket. It never blocks, so it can be
+/// polled from the main loop of the program.
+pub struct ControlServer {
+    path: PathBuf,
+    listener: UnixListener,
+    clients: Vec<ControlClient>,
+}
+
+impl ControlServer {
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<ControlServer> {
+        let path = path.as_ref().to_path_buf();
+
+        // Remove the socket left behind by a previous instance, if any,
+        // but never anything else found at the path.
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(&path)?,
+            Ok(_) => {
+                return Err(io::Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("{} already exists and is not a socket", path.display()),
+                ))
+            }
+            Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+            Err(_) => {}
+        }
+
+        // The socket is created with a restrictive umask, so it is not
+        // accessible by other users even before setting its mode.
+        let umask = unsafe { libc::umask(0o177) };
+        let listener = UnixListener::bind(&path);
+        unsafe { libc::umask(umask) };
+
+        let listener = listener?;
+        listener.set_nonblocking(true)?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+
+        Ok(ControlServer {
+            path,
+            listener,
+            clients: Vec::new(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Accepts the pending connections, and answers all the requests
+    /// received since the last call using the given handler.
+    pub fn poll<F: FnMut(Request) -> Response>(&mut self, mut handler: F) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => match stream.set_nonblocking(true) {
+                    Ok(()) => self.clients.push(ControlClient::new(stream)),
+                    Err(err) => warn!("Cannot set up control client: {}", err),
+                },
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    warn!("Cannot accept control client: {}", err);
+                    break;
+                }
+            }
+        }
+
+        for client in self.clients.iter_mut() {
+            client.flush();
+
+            for line in client.read_lines() {
+                if client.closed {
+                    break;
+                }
+
+                if line.is_empty() {
+                    continue;
+                }
+
+                let response = match serde_json::from_str::<Request>(&line) {
+                    Ok(request) => {
+                        debug!("Control request: {:?}", request);
+                        handler(request)
+                    }
+                    Err(err) => Response::error(format!("Invalid request: {}", err)),
+                };
+                client.send(&response);
+            }
+        }
+
+        self.clients.retain(|client| !client.is_done());
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Sends a single request to the daemon listening on the given
+/// socket, and waits for its response.
+pub fn send_request<P: AsRef<Path>>(path: P, request: &Request) -> io::Result<Response> {
+    let mut stream = UnixStream::connect(path)?;
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response)?;
+    if response.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::UnexpectedEof,
+            "The daemon closed the connection without answering",
+        ));
+    }
+
+    Ok(serde_json::from_str(&response)?)
+}
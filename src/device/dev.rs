@@ -1,7 +1,8 @@
-use crate::types::{Percent, TempCelsius};
+use crate::types::{Percent, Rpm, TempCelsius};
 
 use std::fmt::Debug;
-use std::io::Result;
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
 use udev::Device as UdevDevice;
 
 #[derive(Debug)]
@@ -14,11 +15,62 @@ pub enum PwmMode {
 
 pub trait DeviceBuilder {
     fn from_udev(&self, name: String, device: UdevDevice, dryrun: bool) -> Box<dyn Device>;
+
+    /// Builds a device whose attributes are located in the given
+    /// directory, instead of one found through udev.
+    fn from_path(&self, name: String, path: PathBuf, dryrun: bool) -> Box<dyn Device>;
 }
 
 pub trait Device: Debug {
     fn write_pwm(&self, index: u8, mode: PwmMode) -> Result<()>;
+
+    /// Reads the raw value of the `pwmN_enable` attribute of the
+    /// given output, so it can be put back later with
+    /// `restore_pwm_enable`.
+    fn read_pwm_enable(&self, index: u8) -> Result<String>;
+    fn restore_pwm_enable(&self, index: u8, value: &str) -> Result<()>;
+
+    /// Returns whether the given value read with `read_pwm_enable`
+    /// sets the output to a fixed value, which is not saved with it,
+    /// so restoring it would leave the output pinned. Defaults to the
+    /// manual mode (1) of the sysfs interface of hwmon.
+    fn is_manual_pwm_enable(&self, value: &str) -> bool {
+        value.trim() == "1"
+    }
     fn read_temp(&self, index: u8) -> Result<TempCelsius>;
-    // TODO Add fan_read / voltage_reaḋ for supporting other kind sources.
+    fn read_fan(&self, index: u8) -> Result<Rpm>;
+
+    /// Returns the index of the temperature with the given label, for
+    /// the drivers whose temperatures have one.
+    fn find_temp_by_label(&self, label: &str) -> Result<u8> {
+        Err(Error::new(
+            ErrorKind::Other,
+            format!(
+                "The driver cannot select temperatures by label, as LABEL \"{}\" requires",
+                label
+            ),
+        ))
+    }
+
+    /// Returns the index of the thermal zone with the given type, for
+    /// the drivers of thermal zones.
+    fn find_zone(&self, zone_type: &str) -> Result<u8> {
+        Err(Error::new(
+            ErrorKind::Other,
+            format!(
+                "The driver has no thermal zones, as ZONE \"{}\" requires",
+                zone_type
+            ),
+        ))
+    }
+
+    /// Paths of the attributes backing the sensors and outputs of
+    /// the device, whether they exist or not. Used for verifying the
+    /// configuration without reading or writing the attributes.
+    fn temp_input_path(&self, index: u8) -> PathBuf;
+    fn fan_input_path(&self, index: u8) -> PathBuf;
+    fn pwm_path(&self, index: u8) -> PathBuf;
+
+    // TODO Add voltage_read for supporting other kind sources.
     fn name(&self) -> &str;
 }
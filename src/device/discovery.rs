@@ -0,0 +1,60 @@
+use super::udev_find_with_tags;
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use udev::Device as UdevDevice;
+
+/// Where the attributes of a device can be found.
+pub enum DeviceLocation {
+    Udev(UdevDevice),
+
+    /// A plain directory, laid out as the sysfs directory of a hwmon
+    /// device.
+    Directory(PathBuf),
+}
+
+impl DeviceLocation {
+    /// Directory that contains the attributes of the device.
+    pub fn path(&self) -> &Path {
+        match self {
+            DeviceLocation::Udev(device) => device.syspath(),
+            DeviceLocation::Directory(path) => path,
+        }
+    }
+}
+
+impl Debug for DeviceLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceLocation::Udev(device) => device.devpath().fmt(f),
+            DeviceLocation::Directory(path) => path.fmt(f),
+        }
+    }
+}
+
+/// Defines how the devices declared in the configuration are found
+/// in the system.
+#[derive(Debug, Clone)]
+pub enum DeviceDiscovery {
+    /// Devices are looked up through udev, by their tag.
+    Udev,
+
+    /// Each device is the subdirectory of the given directory named
+    /// after its tag.
+    Directory(PathBuf),
+}
+
+impl DeviceDiscovery {
+    pub fn find(&self, tag: &str) -> Option<DeviceLocation> {
+        match self {
+            DeviceDiscovery::Udev => udev_find_with_tags(vec![tag]).map(DeviceLocation::Udev),
+            DeviceDiscovery::Directory(root) => {
+                let path = root.join(tag);
+                if path.is_dir() {
+                    Some(DeviceLocation::Directory(path))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
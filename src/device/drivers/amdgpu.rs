@@ -0,0 +1,201 @@
+//! Driver for the hwmon interface of AMD graphics cards, exposed by
+//! the `amdgpu` kernel module. The fan of the card is controlled
+//! through `pwm1`, whose range is given by `pwm1_min` and `pwm1_max`.
+//! The card also exposes `fan1_target`, for setting the speed of the
+//! fan in RPM, but it is never written, as doing so overrides the
+//! value written to `pwm1`.
+//!
+//! Not every card exposes all of its temperatures (`edge`, `junction`
+//! and `mem`), nor in the same order, so they are better selected by
+//! their label than by the index of their attribute.
+
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+
+use super::hwmon::HwmonDevice;
+use crate::{
+    device::{Device, DeviceBuilder, PwmMode},
+    types::{Rpm, TempCelsius},
+};
+use udev::Device as UdevDevice;
+
+// Values of the pwmN_enable attribute, as defined by the amdgpu
+// module. No control means the fan runs at full speed.
+const AMDGPU_PWM_MODE_FULL: &str = "0";
+const AMDGPU_PWM_MODE_MANUAL: &str = "1";
+const AMDGPU_PWM_MODE_AUTO: &str = "2";
+
+pub struct Builder;
+
+impl DeviceBuilder for Builder {
+    fn from_udev(&self, name: String, device: UdevDevice, dryrun: bool) -> Box<dyn Device> {
+        Box::new(AmdgpuDevice::from_udev(name, device, dryrun))
+    }
+
+    fn from_path(&self, name: String, path: PathBuf, dryrun: bool) -> Box<dyn Device> {
+        Box::new(AmdgpuDevice::new(
+            name.clone(),
+            HwmonDevice::from_path(name, path, dryrun),
+        ))
+    }
+}
+
+crate::driver_log_define!("amdgpu", amdgpu_);
+
+#[derive(new, Debug)]
+pub struct AmdgpuDevice {
+    name: String,
+    device: HwmonDevice,
+}
+
+impl AmdgpuDevice {
+    fn from_udev(name: String, device: UdevDevice, dryrun: bool) -> AmdgpuDevice {
+        AmdgpuDevice::new(name.clone(), HwmonDevice::from_udev(name, device, dryrun))
+    }
+
+    /// Reads a pwm limit of the card, falling back to the given value
+    /// if the card doesn't have it.
+    fn read_pwm_limit(&self, attr: &str, default: u8) -> Result<u8> {
+        if !self.device.has_attr(attr) {
+            return Ok(default);
+        }
+
+        self.device
+            .read_attr(attr)?
+            .parse::<u8>()
+            .map_err(|err| Error::new(ErrorKind::Other, err))
+    }
+}
+
+impl Device for AmdgpuDevice {
+    fn write_pwm(&self, index: u8, mode: PwmMode) -> Result<()> {
+        match mode {
+            PwmMode::Auto => self.device.write_pwm_enable(index, AMDGPU_PWM_MODE_AUTO),
+            PwmMode::Full => self.device.write_pwm_enable(index, AMDGPU_PWM_MODE_FULL),
+            PwmMode::ManualPercent(percent) => {
+                let min = self.read_pwm_limit(&format!("pwm{}_min", index), u8::MIN)?;
+                let max = self.read_pwm_limit(&format!("pwm{}_max", index), u8::MAX)?;
+                amdgpu_debug!(@ self.name;
+                    "Request PWM {} set to {} (range {}-{}).",
+                    index,
+                    percent,
+                    min,
+                    max
+                );
+                self.device.write_pwm_enable_and_value(
+                    index,
+                    AMDGPU_PWM_MODE_MANUAL,
+                    percent.point_at_range(min, max),
+                )
+            }
+            PwmMode::ManualAbs(value) => {
+                self.device
+                    .write_pwm_enable_and_value(index, AMDGPU_PWM_MODE_MANUAL, value)
+            }
+        }
+    }
+
+    fn read_pwm_enable(&self, index: u8) -> Result<String> {
+        self.device.read_pwm_enable(index)
+    }
+
+    fn restore_pwm_enable(&self, index: u8, value: &str) -> Result<()> {
+        self.device.restore_pwm_enable(index, value)
+    }
+
+    fn read_temp(&self, index: u8) -> Result<TempCelsius> {
+        self.device.read_temp(index)
+    }
+
+    fn read_fan(&self, index: u8) -> Result<Rpm> {
+        self.device.read_fan(index)
+    }
+
+    fn find_temp_by_label(&self, label: &str) -> Result<u8> {
+        self.device.find_temp_by_label(label)
+    }
+
+    fn temp_input_path(&self, index: u8) -> PathBuf {
+        self.device.temp_input_path(index)
+    }
+
+    fn fan_input_path(&self, index: u8) -> PathBuf {
+        self.device.fan_input_path(index)
+    }
+
+    fn pwm_path(&self, index: u8) -> PathBuf {
+        self.device.pwm_path(index)
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Percent;
+    use crate::util::TestDir;
+    use std::convert::TryFrom;
+
+    /// Creates a directory laid out as the hwmon directory of an
+    /// amdgpu card, with the given temperature labels.
+    fn fake_card(name: &str, labels: &[&str]) -> TestDir {
+        let dir = TestDir::new(&format!("amdgpu-{}", name));
+        dir.write_attr("name", "amdgpu");
+        dir.write_attr("pwm1", "80");
+        dir.write_attr("pwm1_enable", "2");
+        dir.write_attr("pwm1_min", "0");
+        dir.write_attr("pwm1_max", "255");
+        dir.write_attr("fan1_input", "1200");
+        dir.write_attr("fan1_target", "1200");
+        dir.write_attr("fan1_enable", "0");
+        for (index, label) in labels.iter().enumerate() {
+            dir.write_attr(format!("temp{}_label", index + 1), label);
+            dir.write_attr(
+                format!("temp{}_input", index + 1),
+                &format!("{}000", 40 + index),
+            );
+        }
+
+        dir
+    }
+
+    #[test]
+    fn pwm_modes() {
+        let dir = fake_card("modes", &["edge"]);
+        let device = Builder.from_path("gpu".into(), dir.path().to_path_buf(), false);
+
+        device
+            .write_pwm(1, PwmMode::ManualPercent(Percent::try_from(50).unwrap()))
+            .unwrap();
+        assert_eq!(dir.read_attr("pwm1_enable"), AMDGPU_PWM_MODE_MANUAL);
+        assert_eq!(dir.read_attr("pwm1"), "127");
+
+        device.write_pwm(1, PwmMode::Full).unwrap();
+        assert_eq!(dir.read_attr("pwm1_enable"), AMDGPU_PWM_MODE_FULL);
+
+        device.write_pwm(1, PwmMode::Auto).unwrap();
+        assert_eq!(dir.read_attr("pwm1_enable"), AMDGPU_PWM_MODE_AUTO);
+
+        // The fan is only ever controlled through pwm1.
+        assert_eq!(dir.read_attr("fan1_target"), "1200");
+        assert_eq!(device.read_fan(1).unwrap().rpm(), 1200);
+    }
+
+    #[test]
+    fn temps_by_label() {
+        let dir = fake_card("labels", &["junction", "edge"]);
+        let device = Builder.from_path("gpu".into(), dir.path().to_path_buf(), false);
+
+        assert_eq!(device.find_temp_by_label("junction").unwrap(), 1);
+        assert_eq!(device.find_temp_by_label("edge").unwrap(), 2);
+        assert_eq!(device.read_temp(2).unwrap().mcelsius(), 41_000);
+        assert_eq!(device.temp_input_path(2), dir.join("temp2_input"));
+        assert_eq!(
+            device.find_temp_by_label("mem").unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+    }
+}
@@ -1,16 +1,28 @@
+use guard::guard;
 use udev::Device as UdevDevice;
 
 use crate::{
     device::{Device, DeviceBuilder, PwmMode},
-    types::TempCelsius,
+    types::{Rpm, TempCelsius},
 };
-use std::io::{Error, Result};
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+
+// Values of the pwmN_enable attribute, as defined by the sysfs
+// interface of the hwmon subsystem.
+const HWMON_PWM_MODE_MANUAL: &str = "1";
+const HWMON_PWM_MODE_AUTO: &str = "2";
+
 pub struct Builder;
 
 impl DeviceBuilder for Builder {
     fn from_udev(&self, name: String, device: UdevDevice, dryrun: bool) -> Box<dyn Device> {
         Box::new(HwmonDevice::from_udev(name, device, dryrun))
     }
+
+    fn from_path(&self, name: String, path: PathBuf, dryrun: bool) -> Box<dyn Device> {
+        Box::new(HwmonDevice::from_path(name, path, dryrun))
+    }
 }
 
 crate::driver_log_define!("hwmon", hwmon_);
@@ -18,14 +30,16 @@ crate::driver_log_define!("hwmon", hwmon_);
 #[derive(new)]
 pub struct HwmonDevice {
     name: String,
-    device: UdevDevice,
+
+    /// Directory that contains the attributes of the device.
+    path: PathBuf,
     dryrun: bool,
 }
 
 impl std::fmt::Debug for HwmonDevice {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("HwmonGenericDevice")
-            .field("device", &self.device.devpath())
+            .field("path", &self.path)
             .finish()
     }
 }
@@ -46,7 +60,11 @@ macro_rules! log_write {
 
 impl HwmonDevice {
     pub fn from_udev(name: String, device: UdevDevice, dryrun: bool) -> HwmonDevice {
-        HwmonDevice::new(name, device, dryrun)
+        HwmonDevice::from_path(name, device.syspath().to_path_buf(), dryrun)
+    }
+
+    pub fn from_path(name: String, path: PathBuf, dryrun: bool) -> HwmonDevice {
+        HwmonDevice::new(name, path, dryrun)
     }
 
     fn pwm_enable_attr(num: u8) -> String {
@@ -61,9 +79,13 @@ impl HwmonDevice {
         return format!("temp{}_input", num);
     }
 
+    fn fan_input_attr(num: u8) -> String {
+        return format!("fan{}_input", num);
+    }
+
     pub fn write_raw_pwm(&self, num: u8, value: u8) -> Result<()> {
         let attr_value = Self::pwm_attr(num);
-        let path = self.device.syspath().join(&attr_value);
+        let path = self.path.join(&attr_value);
         log_write!(self, value, attr_value);
         run_action!(self, {
             std::fs::write(&path, format!("{}\n", value))?;
@@ -74,7 +96,7 @@ impl HwmonDevice {
 
     pub fn write_pwm_enable(&self, num: u8, enable: &str) -> Result<()> {
         let attr_enable = Self::pwm_enable_attr(num);
-        let path = self.device.syspath().join(&attr_enable);
+        let path = self.path.join(&attr_enable);
         log_write!(self, enable, attr_enable);
         run_action!(self, {
             std::fs::write(&path, format!("{}\n", enable))?;
@@ -88,8 +110,53 @@ impl HwmonDevice {
         self.write_raw_pwm(num, value)
     }
 
+    /// Switches the given output to manual mode, if it has a
+    /// pwmN_enable attribute and it is not already in that mode. This
+    /// is required for taking back the control of outputs that were
+    /// previously set to automatic mode.
+    fn ensure_manual_mode(&self, num: u8) -> Result<()> {
+        let attr_enable = Self::pwm_enable_attr(num);
+        if self.has_attr(&attr_enable) && self.read_attr(&attr_enable)? != HWMON_PWM_MODE_MANUAL {
+            self.write_pwm_enable(num, HWMON_PWM_MODE_MANUAL)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the index of the channel of the given kind (`temp`,
+    /// `fan`...) whose `<kind>N_label` attribute is the given label,
+    /// if any. If several channels share the label, the lowest index
+    /// is returned.
+    pub fn find_label(&self, kind: &str, label: &str) -> Result<Option<u8>> {
+        let mut found: Option<u8> = None;
+        for entry in std::fs::read_dir(&self.path)? {
+            let file_name = entry?.file_name();
+            let file_name = file_name.to_string_lossy();
+
+            let index = file_name
+                .strip_prefix(kind)
+                .and_then(|rest| rest.strip_suffix("_label"))
+                .and_then(|index| index.parse::<u8>().ok());
+            guard!(let Some(index) = index else {
+                continue;
+            });
+
+            if self.read_attr(&file_name)?.trim() == label
+                && found.map_or(true, |found| index < found)
+            {
+                found = Some(index);
+            }
+        }
+
+        Ok(found)
+    }
+
+    pub fn has_attr(&self, name: &str) -> bool {
+        self.path.join(name).exists()
+    }
+
     pub fn read_attr(&self, name: &str) -> Result<String> {
-        let path = self.device.syspath().join(name);
+        let path = self.path.join(name);
         let s = String::from_utf8_lossy(&std::fs::read(&path)?).into_owned();
         if s.ends_with("\n") {
             Ok((&s[0..s.len() - 1]).into())
@@ -102,8 +169,15 @@ impl HwmonDevice {
 impl Device for HwmonDevice {
     fn write_pwm(&self, index: u8, mode: PwmMode) -> Result<()> {
         match mode {
-            PwmMode::Auto | PwmMode::Full => {
-                unimplemented!("Unsupported PWM mode for device: {:?}", mode)
+            PwmMode::Auto => self.write_pwm_enable(index, HWMON_PWM_MODE_AUTO),
+            PwmMode::Full => {
+                // Not every hwmon device supports the "no control"
+                // mode (0), nor even has a pwmN_enable attribute, so
+                // just write the maximum value in manual mode.
+                if self.has_attr(&Self::pwm_enable_attr(index)) {
+                    self.write_pwm_enable(index, HWMON_PWM_MODE_MANUAL)?;
+                }
+                self.write_raw_pwm(index, u8::MAX)
             }
             PwmMode::ManualAbs(value) => self.write_raw_pwm(index, value),
             PwmMode::ManualPercent(value) => {
@@ -113,11 +187,20 @@ impl Device for HwmonDevice {
                     &self.name,
                     value
                 );
+                self.ensure_manual_mode(index)?;
                 self.write_raw_pwm(index, value.point_at_range(0u8, 255u8))
             }
         }
     }
 
+    fn read_pwm_enable(&self, index: u8) -> Result<String> {
+        self.read_attr(&Self::pwm_enable_attr(index))
+    }
+
+    fn restore_pwm_enable(&self, index: u8, value: &str) -> Result<()> {
+        self.write_pwm_enable(index, value)
+    }
+
     fn read_temp(&self, index: u8) -> Result<TempCelsius> {
         self.read_attr(&Self::temp_input_attr(index))?
             .parse::<i32>()
@@ -125,6 +208,38 @@ impl Device for HwmonDevice {
             .map_err(|err| Error::new(std::io::ErrorKind::Other, err))
     }
 
+    fn read_fan(&self, index: u8) -> Result<Rpm> {
+        self.read_attr(&Self::fan_input_attr(index))?
+            .parse::<u32>()
+            .map(|rpm| Rpm::from_rpm(rpm))
+            .map_err(|err| Error::new(std::io::ErrorKind::Other, err))
+    }
+
+    /// Looks up the temperature with the given label. If several
+    /// temperatures share it, the lowest index is returned.
+    fn find_temp_by_label(&self, label: &str) -> Result<u8> {
+        let index = self.find_label("temp", label)?;
+        hwmon_debug!(@ self.name; "Label \"{}\" found at {:?}.", label, index);
+        index.ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("No temperature labeled \"{}\"", label),
+            )
+        })
+    }
+
+    fn temp_input_path(&self, index: u8) -> PathBuf {
+        self.path.join(Self::temp_input_attr(index))
+    }
+
+    fn fan_input_path(&self, index: u8) -> PathBuf {
+        self.path.join(Self::fan_input_attr(index))
+    }
+
+    fn pwm_path(&self, index: u8) -> PathBuf {
+        self.path.join(Self::pwm_attr(index))
+    }
+
     fn name(&self) -> &str {
         &self.name
     }
@@ -0,0 +1,183 @@
+//! Driver for the ITE Super I/O chips, handled by the `it87` kernel
+//! module. Unlike on other chips, the automatic mode of an output is
+//! only available if the chip has its `pwmN_auto_*` attributes, which
+//! hold the automatic fan curve; otherwise the output is set to full
+//! speed when asked for automatic mode.
+
+use std::io::Result;
+use std::path::PathBuf;
+
+use super::hwmon::HwmonDevice;
+use crate::{
+    device::{Device, DeviceBuilder, PwmMode},
+    types::{Rpm, TempCelsius},
+};
+use udev::Device as UdevDevice;
+
+const IT87_PWM_MODE_FULL: &str = "0";
+const IT87_PWM_MODE_MANUAL: &str = "1";
+const IT87_PWM_MODE_AUTO: &str = "2";
+
+pub struct Builder;
+
+impl DeviceBuilder for Builder {
+    fn from_udev(&self, name: String, device: UdevDevice, dryrun: bool) -> Box<dyn Device> {
+        Box::new(It87Device::from_udev(name, device, dryrun))
+    }
+
+    fn from_path(&self, name: String, path: PathBuf, dryrun: bool) -> Box<dyn Device> {
+        Box::new(It87Device::new(
+            name.clone(),
+            HwmonDevice::from_path(name, path, dryrun),
+        ))
+    }
+}
+
+crate::driver_log_define!("it87", it87_);
+
+#[derive(new, Debug)]
+pub struct It87Device {
+    name: String,
+    device: HwmonDevice,
+}
+
+impl It87Device {
+    fn from_udev(name: String, device: UdevDevice, dryrun: bool) -> It87Device {
+        It87Device::new(name.clone(), HwmonDevice::from_udev(name, device, dryrun))
+    }
+
+    /// Returns whether the given output supports the automatic mode.
+    fn supports_auto(&self, index: u8) -> bool {
+        self.device
+            .has_attr(&format!("pwm{}_auto_channels_temp", index))
+    }
+}
+
+impl Device for It87Device {
+    fn write_pwm(&self, index: u8, mode: PwmMode) -> Result<()> {
+        match mode {
+            PwmMode::Auto if self.supports_auto(index) => {
+                self.device.write_pwm_enable(index, IT87_PWM_MODE_AUTO)
+            }
+            PwmMode::Auto => {
+                it87_warn!(@ self.name;
+                    "PWM {} has no automatic mode. Setting it to full speed instead.",
+                    index
+                );
+                self.device.write_pwm_enable(index, IT87_PWM_MODE_FULL)
+            }
+            PwmMode::Full => self.device.write_pwm_enable(index, IT87_PWM_MODE_FULL),
+            PwmMode::ManualPercent(percent) => {
+                it87_debug!(@ self.name; "Request PWM {} set to {}.", index, percent);
+                self.device.write_pwm_enable_and_value(
+                    index,
+                    IT87_PWM_MODE_MANUAL,
+                    percent.point_at_range(0u8, 255u8),
+                )
+            }
+            PwmMode::ManualAbs(value) => {
+                self.device
+                    .write_pwm_enable_and_value(index, IT87_PWM_MODE_MANUAL, value)
+            }
+        }
+    }
+
+    fn read_pwm_enable(&self, index: u8) -> Result<String> {
+        self.device.read_pwm_enable(index)
+    }
+
+    fn restore_pwm_enable(&self, index: u8, value: &str) -> Result<()> {
+        self.device.restore_pwm_enable(index, value)
+    }
+
+    fn read_temp(&self, index: u8) -> Result<TempCelsius> {
+        self.device.read_temp(index)
+    }
+
+    fn read_fan(&self, index: u8) -> Result<Rpm> {
+        self.device.read_fan(index)
+    }
+
+    fn find_temp_by_label(&self, label: &str) -> Result<u8> {
+        self.device.find_temp_by_label(label)
+    }
+
+    fn temp_input_path(&self, index: u8) -> PathBuf {
+        self.device.temp_input_path(index)
+    }
+
+    fn fan_input_path(&self, index: u8) -> PathBuf {
+        self.device.fan_input_path(index)
+    }
+
+    fn pwm_path(&self, index: u8) -> PathBuf {
+        self.device.pwm_path(index)
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Percent;
+    use crate::util::TestDir;
+    use std::convert::TryFrom;
+
+    /// Creates a directory laid out as the hwmon directory of an it87
+    /// chip, with two outputs. Only the first one has an automatic
+    /// mode.
+    fn fake_chip(name: &str) -> TestDir {
+        let dir = TestDir::new(&format!("it87-{}", name));
+        dir.write_attr("name", "it8686");
+        for index in 1..=2 {
+            dir.write_attr(format!("pwm{}", index), "128");
+            dir.write_attr(format!("pwm{}_enable", index), IT87_PWM_MODE_MANUAL);
+        }
+        dir.write_attr("pwm1_auto_channels_temp", "1");
+        dir
+    }
+
+    #[test]
+    fn pwm_modes() {
+        let dir = fake_chip("modes");
+        let device = Builder.from_path("mobo".into(), dir.path().to_path_buf(), false);
+
+        device.write_pwm(1, PwmMode::Full).unwrap();
+        assert_eq!(dir.read_attr("pwm1_enable"), IT87_PWM_MODE_FULL);
+
+        device
+            .write_pwm(1, PwmMode::ManualPercent(Percent::try_from(50).unwrap()))
+            .unwrap();
+        assert_eq!(dir.read_attr("pwm1_enable"), IT87_PWM_MODE_MANUAL);
+        assert_eq!(dir.read_attr("pwm1"), "127");
+
+        device.write_pwm(1, PwmMode::Auto).unwrap();
+        assert_eq!(dir.read_attr("pwm1_enable"), IT87_PWM_MODE_AUTO);
+    }
+
+    #[test]
+    fn auto_without_curve() {
+        let dir = fake_chip("no-auto");
+        let device = Builder.from_path("mobo".into(), dir.path().to_path_buf(), false);
+
+        device.write_pwm(2, PwmMode::Auto).unwrap();
+        assert_eq!(dir.read_attr("pwm2_enable"), IT87_PWM_MODE_FULL);
+    }
+
+    #[test]
+    fn restore_mode() {
+        let dir = fake_chip("restore");
+        let device = Builder.from_path("mobo".into(), dir.path().to_path_buf(), false);
+
+        let saved = device.read_pwm_enable(1).unwrap();
+        device.write_pwm(1, PwmMode::Full).unwrap();
+        device.restore_pwm_enable(1, &saved).unwrap();
+        assert_eq!(dir.read_attr("pwm1_enable"), IT87_PWM_MODE_MANUAL);
+
+        device.restore_pwm_enable(1, IT87_PWM_MODE_AUTO).unwrap();
+        assert_eq!(dir.read_attr("pwm1_enable"), IT87_PWM_MODE_AUTO);
+    }
+}
@@ -0,0 +1,144 @@
+//! Driver for devices simulated in a plain directory, laid out as the
+//! sysfs directory of a hwmon device (`tempN_input`, `fanN_input`,
+//! `pwmN` and `pwmN_enable`). Useful for running the program in
+//! systems without the actual hardware, with the values of the
+//! sensors controlled by a script.
+
+use std::io::Result;
+use std::path::PathBuf;
+
+use super::hwmon::HwmonDevice;
+use crate::{
+    device::{Device, DeviceBuilder, PwmMode},
+    types::{Rpm, TempCelsius},
+};
+use udev::Device as UdevDevice;
+
+pub struct Builder;
+
+impl DeviceBuilder for Builder {
+    fn from_udev(&self, name: String, device: UdevDevice, dryrun: bool) -> Box<dyn Device> {
+        self.from_path(name, device.syspath().to_path_buf(), dryrun)
+    }
+
+    fn from_path(&self, name: String, path: PathBuf, dryrun: bool) -> Box<dyn Device> {
+        Box::new(MockDevice::new(
+            name.clone(),
+            HwmonDevice::from_path(name, path, dryrun),
+        ))
+    }
+}
+
+crate::driver_log_define!("mock", mock_);
+
+#[derive(new, Debug)]
+pub struct MockDevice {
+    name: String,
+    device: HwmonDevice,
+}
+
+impl Device for MockDevice {
+    fn write_pwm(&self, index: u8, mode: PwmMode) -> Result<()> {
+        mock_debug!(@ self.name; "Request PWM {} set to {:?}.", index, mode);
+        self.device.write_pwm(index, mode)
+    }
+
+    fn read_pwm_enable(&self, index: u8) -> Result<String> {
+        self.device.read_pwm_enable(index)
+    }
+
+    fn restore_pwm_enable(&self, index: u8, value: &str) -> Result<()> {
+        self.device.restore_pwm_enable(index, value)
+    }
+
+    fn read_temp(&self, index: u8) -> Result<TempCelsius> {
+        self.device.read_temp(index)
+    }
+
+    fn read_fan(&self, index: u8) -> Result<Rpm> {
+        self.device.read_fan(index)
+    }
+
+    fn find_temp_by_label(&self, label: &str) -> Result<u8> {
+        self.device.find_temp_by_label(label)
+    }
+
+    fn temp_input_path(&self, index: u8) -> PathBuf {
+        self.device.temp_input_path(index)
+    }
+
+    fn fan_input_path(&self, index: u8) -> PathBuf {
+        self.device.fan_input_path(index)
+    }
+
+    fn pwm_path(&self, index: u8) -> PathBuf {
+        self.device.pwm_path(index)
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{model::ThermalProgram, SensorSelector};
+    use crate::device::DeviceDiscovery;
+    use crate::engine::RuleEngine;
+    use crate::types::SensorValue;
+    use crate::util::TestDir;
+    use std::{rc::Rc, time::Instant};
+
+    /// Evaluates the rules once, reading the sensors from the device
+    /// and writing the resulting values to its outputs.
+    fn run_once(engine: &mut RuleEngine, program: &ThermalProgram, device: &dyn Device) {
+        let evaluation = engine.evaluate(&program.rules, Instant::now(), |sensor| {
+            match &sensor.selector {
+                SensorSelector::Index(index) => device
+                    .read_temp(*index)
+                    .map(SensorValue::Temp)
+                    .map_err(Rc::new),
+                selector => panic!("Unexpected sensor selector: {}", selector),
+            }
+        });
+
+        for (key, value) in evaluation.combined.output_values.iter() {
+            device
+                .write_pwm(key.output.index, PwmMode::ManualPercent(value.value))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn rules_on_discovered_directory() {
+        let root = TestDir::new("mock-discovery");
+        root.write_attr("board/temp1_input", "35000");
+        root.write_attr("board/pwm1", "0");
+        root.write_attr("board/pwm1_enable", "2");
+
+        let discovery = DeviceDiscovery::Directory(root.path().to_path_buf());
+        assert!(discovery.find("missing").is_none());
+        let location = discovery.find("board").unwrap();
+        let device = Builder.from_path("board".into(), location.path().to_path_buf(), false);
+
+        let (program, _) = crate::config::compile(
+            "test.conf",
+            "DEFINE DEVICE `board` UDEV TAG \"board\" DRIVER \"mock\";\n\
+             DEFINE SENSOR `temp` DEVICE `board` TYPE TERMISTOR INDEX 1;\n\
+             DEFINE OUTPUT `fan` DEVICE `board` TYPE PWM INDEX 1;\n\
+             WHEN `temp` > 40 DO SET `fan` TO 60%; END",
+        )
+        .unwrap();
+        let mut engine = RuleEngine::default();
+
+        run_once(&mut engine, &program, device.as_ref());
+        assert_eq!(root.read_attr("board/pwm1"), "0");
+        assert_eq!(root.read_attr("board/pwm1_enable"), "2");
+
+        root.write_attr("board/temp1_input", "45000");
+        run_once(&mut engine, &program, device.as_ref());
+        assert_eq!(root.read_attr("board/pwm1"), "153");
+        assert_eq!(root.read_attr("board/pwm1_enable"), "1");
+    }
+}
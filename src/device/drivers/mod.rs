@@ -1,5 +1,10 @@
 mod devlog;
 pub use devlog::*;
 
+pub mod amdgpu;
 pub mod hwmon;
+pub mod it87;
+pub mod mock;
 pub mod nct6775;
+pub mod thermal_zone;
+pub mod thinkpad;
@@ -1,9 +1,10 @@
 use std::io::Result;
+use std::path::PathBuf;
 
 use super::hwmon::HwmonDevice;
 use crate::{
     device::{Device, DeviceBuilder, PwmMode},
-    types::TempCelsius,
+    types::{Rpm, TempCelsius},
 };
 use udev::Device as UdevDevice;
 
@@ -17,6 +18,13 @@ impl DeviceBuilder for Builder {
     fn from_udev(&self, name: String, device: UdevDevice, dryrun: bool) -> Box<dyn Device> {
         Box::new(Nct6775Device::from_udev(name, device, dryrun))
     }
+
+    fn from_path(&self, name: String, path: PathBuf, dryrun: bool) -> Box<dyn Device> {
+        Box::new(Nct6775Device::new(
+            name.clone(),
+            HwmonDevice::from_path(name, path, dryrun),
+        ))
+    }
 }
 
 crate::driver_log_define!("nct6775", nct6775_);
@@ -50,10 +58,38 @@ impl Device for Nct6775Device {
         }
     }
 
+    fn read_pwm_enable(&self, index: u8) -> Result<String> {
+        self.device.read_pwm_enable(index)
+    }
+
+    fn restore_pwm_enable(&self, index: u8, value: &str) -> Result<()> {
+        self.device.restore_pwm_enable(index, value)
+    }
+
     fn read_temp(&self, index: u8) -> Result<TempCelsius> {
         self.device.read_temp(index)
     }
 
+    fn read_fan(&self, index: u8) -> Result<Rpm> {
+        self.device.read_fan(index)
+    }
+
+    fn find_temp_by_label(&self, label: &str) -> Result<u8> {
+        self.device.find_temp_by_label(label)
+    }
+
+    fn temp_input_path(&self, index: u8) -> PathBuf {
+        self.device.temp_input_path(index)
+    }
+
+    fn fan_input_path(&self, index: u8) -> PathBuf {
+        self.device.fan_input_path(index)
+    }
+
+    fn pwm_path(&self, index: u8) -> PathBuf {
+        self.device.pwm_path(index)
+    }
+
     fn name(&self) -> &str {
         &self.name
     }
@@ -0,0 +1,153 @@
+//! Driver for the thermal zones of the kernel, found on the `thermal`
+//! subsystem of udev (`/sys/class/thermal/thermal_zoneN`), like ACPI
+//! zones or `x86_pkg_temp`. The device is the directory holding the
+//! zones, so the udev tag may be assigned to any of them, and its
+//! sensors are the zones, selected by their `type` with ZONE. The
+//! index of a zone is the N of its directory. Thermal zones have no
+//! fans or outputs.
+
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+
+use crate::{
+    device::{Device, DeviceBuilder, PwmMode},
+    types::{Rpm, TempCelsius},
+};
+use udev::Device as UdevDevice;
+
+const THERMAL_ZONE_PREFIX: &str = "thermal_zone";
+
+pub struct Builder;
+
+impl DeviceBuilder for Builder {
+    fn from_udev(&self, name: String, device: UdevDevice, _dryrun: bool) -> Box<dyn Device> {
+        // The zones are siblings on the thermal class directory.
+        let path = device
+            .syspath()
+            .parent()
+            .map(|path| path.to_path_buf())
+            .unwrap_or_else(|| device.syspath().to_path_buf());
+        Box::new(ThermalZoneDevice::new(name, path))
+    }
+
+    fn from_path(&self, name: String, path: PathBuf, _dryrun: bool) -> Box<dyn Device> {
+        Box::new(ThermalZoneDevice::new(name, path))
+    }
+}
+
+crate::driver_log_define!("thermal_zone", thermal_zone_);
+
+#[derive(new, Debug)]
+pub struct ThermalZoneDevice {
+    name: String,
+
+    /// Directory that contains the `thermal_zoneN` directories.
+    path: PathBuf,
+}
+
+impl ThermalZoneDevice {
+    fn zone_path(&self, index: u8) -> PathBuf {
+        self.path.join(format!("{}{}", THERMAL_ZONE_PREFIX, index))
+    }
+
+    fn unsupported(&self, what: &str) -> Error {
+        Error::new(ErrorKind::Other, format!("Thermal zones have no {}", what))
+    }
+}
+
+impl Device for ThermalZoneDevice {
+    fn write_pwm(&self, _index: u8, _mode: PwmMode) -> Result<()> {
+        Err(self.unsupported("outputs"))
+    }
+
+    fn read_pwm_enable(&self, _index: u8) -> Result<String> {
+        Err(self.unsupported("outputs"))
+    }
+
+    fn restore_pwm_enable(&self, _index: u8, _value: &str) -> Result<()> {
+        Err(self.unsupported("outputs"))
+    }
+
+    fn read_temp(&self, index: u8) -> Result<TempCelsius> {
+        std::fs::read_to_string(self.temp_input_path(index))?
+            .trim()
+            .parse::<i32>()
+            .map(TempCelsius::from_mcelsius)
+            .map_err(|err| Error::new(ErrorKind::Other, err))
+    }
+
+    fn read_fan(&self, _index: u8) -> Result<Rpm> {
+        Err(self.unsupported("fans"))
+    }
+
+    /// Looks up the zone with the given type. If several zones share
+    /// it, the lowest index is returned.
+    fn find_zone(&self, zone_type: &str) -> Result<u8> {
+        let mut found: Option<u8> = None;
+        for entry in std::fs::read_dir(&self.path)? {
+            let entry = entry?;
+            let index = entry
+                .file_name()
+                .to_string_lossy()
+                .strip_prefix(THERMAL_ZONE_PREFIX)
+                .and_then(|index| index.parse::<u8>().ok());
+
+            if let Some(index) = index {
+                let entry_type = std::fs::read_to_string(entry.path().join("type"))?;
+                if entry_type.trim() == zone_type && found.map_or(true, |found| index < found) {
+                    found = Some(index);
+                }
+            }
+        }
+
+        thermal_zone_debug!(@ self.name; "Zone \"{}\" found at {:?}.", zone_type, found);
+        found.ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("No thermal zone of type \"{}\"", zone_type),
+            )
+        })
+    }
+
+    fn temp_input_path(&self, index: u8) -> PathBuf {
+        self.zone_path(index).join("temp")
+    }
+
+    fn fan_input_path(&self, index: u8) -> PathBuf {
+        self.zone_path(index).join("fan")
+    }
+
+    fn pwm_path(&self, index: u8) -> PathBuf {
+        self.zone_path(index).join("pwm")
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TestDir;
+
+    #[test]
+    fn zones_by_type() {
+        let dir = TestDir::new("thermal-zone");
+        let zones = [(0, "acpitz", "27800"), (2, "x86_pkg_temp", "45000")];
+        for (index, zone_type, temp) in zones.iter() {
+            dir.write_attr(format!("thermal_zone{}/type", index), zone_type);
+            dir.write_attr(format!("thermal_zone{}/temp", index), temp);
+        }
+        std::fs::create_dir_all(dir.join("cooling_device0")).unwrap();
+
+        let device = Builder.from_path("zones".into(), dir.path().to_path_buf(), false);
+        assert_eq!(device.find_zone("x86_pkg_temp").unwrap(), 2);
+        assert_eq!(device.read_temp(2).unwrap().mcelsius(), 45_000);
+        assert_eq!(device.find_zone("acpitz").unwrap(), 0);
+        assert_eq!(
+            device.find_zone("iwlwifi_1").unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+    }
+}
@@ -0,0 +1,264 @@
+//! Driver for the fan of ThinkPad laptops, controlled through the
+//! `/proc/acpi/ibm/fan` interface of the `thinkpad_acpi` kernel
+//! module. The fan has no pwm attribute, but a set of discrete levels:
+//! from 0 (stopped) to 7, `auto` and `full-speed`. Manual values are
+//! mapped to the lowest level that is at least as fast as requested.
+//! Temperatures and fan speeds are read from the hwmon device of the
+//! module, which is the one the udev tag must be assigned to.
+//!
+//! The module only accepts commands if it is loaded with the
+//! `fan_control=1` parameter. When run on a directory instead of
+//! through udev, the interface and the parameter are the `fan` and
+//! `fan_control` files of that directory.
+
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+
+use super::hwmon::HwmonDevice;
+use crate::{
+    device::{Device, DeviceBuilder, PwmMode},
+    types::{Rpm, TempCelsius},
+};
+use udev::Device as UdevDevice;
+
+const THINKPAD_FAN_PATH: &str = "/proc/acpi/ibm/fan";
+const THINKPAD_FAN_CONTROL_PATH: &str = "/sys/module/thinkpad_acpi/parameters/fan_control";
+
+const THINKPAD_LEVEL_AUTO: &str = "auto";
+const THINKPAD_LEVEL_FULL: &str = "full-speed";
+const THINKPAD_MAX_LEVEL: u32 = 7;
+
+pub struct Builder;
+
+impl DeviceBuilder for Builder {
+    fn from_udev(&self, name: String, device: UdevDevice, dryrun: bool) -> Box<dyn Device> {
+        Box::new(ThinkpadDevice::new(
+            name.clone(),
+            HwmonDevice::from_udev(name, device, dryrun),
+            THINKPAD_FAN_PATH.into(),
+            THINKPAD_FAN_CONTROL_PATH.into(),
+            dryrun,
+        ))
+    }
+
+    fn from_path(&self, name: String, path: PathBuf, dryrun: bool) -> Box<dyn Device> {
+        Box::new(ThinkpadDevice::new(
+            name.clone(),
+            HwmonDevice::from_path(name, path.clone(), dryrun),
+            path.join("fan"),
+            path.join("fan_control"),
+            dryrun,
+        ))
+    }
+}
+
+crate::driver_log_define!("thinkpad", thinkpad_);
+
+#[derive(new, Debug)]
+pub struct ThinkpadDevice {
+    name: String,
+    device: HwmonDevice,
+
+    /// File the fan commands are written to.
+    fan_path: PathBuf,
+
+    /// File holding the `fan_control` parameter of the module.
+    fan_control_path: PathBuf,
+    dryrun: bool,
+}
+
+impl ThinkpadDevice {
+    /// Fails with a descriptive error if the module doesn't accept
+    /// fan commands.
+    fn ensure_fan_control(&self) -> Result<()> {
+        let enabled = std::fs::read_to_string(&self.fan_control_path)
+            .map(|value| matches!(value.trim(), "Y" | "1"))
+            .unwrap_or(false);
+
+        if enabled {
+            Ok(())
+        } else {
+            Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "Fan control is disabled on thinkpad_acpi. Load the module with the \
+                 fan_control=1 parameter, e.g. adding `options thinkpad_acpi fan_control=1` \
+                 to a file in /etc/modprobe.d",
+            ))
+        }
+    }
+
+    fn write_level(&self, level: &str) -> Result<()> {
+        self.ensure_fan_control()?;
+
+        thinkpad_debug!(@ self.name;
+            "write 'level {}' to `{}`.",
+            level,
+            self.fan_path.display()
+        );
+        if !self.dryrun {
+            std::fs::write(&self.fan_path, format!("level {}\n", level))?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the lowest level that runs the fan at least at the
+    /// given fraction of its maximum speed.
+    fn level_for(value: u32, max: u32) -> u32 {
+        (value * THINKPAD_MAX_LEVEL + max - 1) / max
+    }
+}
+
+fn ensure_single_fan(index: u8) -> Result<()> {
+    if index == 1 {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "ThinkPads have a single fan output, but {} requested",
+                index
+            ),
+        ))
+    }
+}
+
+impl Device for ThinkpadDevice {
+    fn write_pwm(&self, index: u8, mode: PwmMode) -> Result<()> {
+        ensure_single_fan(index)?;
+        match mode {
+            PwmMode::Auto => self.write_level(THINKPAD_LEVEL_AUTO),
+            PwmMode::Full => self.write_level(THINKPAD_LEVEL_FULL),
+            PwmMode::ManualPercent(percent) => {
+                let level = Self::level_for(percent.value() as u32, 100);
+                thinkpad_debug!(@ self.name;
+                    "Request fan set to {} (level {}).",
+                    percent,
+                    level
+                );
+                self.write_level(&level.to_string())
+            }
+            PwmMode::ManualAbs(value) => {
+                self.write_level(&Self::level_for(value as u32, u8::MAX as u32).to_string())
+            }
+        }
+    }
+
+    /// Reads the current level of the fan, as reported on the `level:`
+    /// line of the interface.
+    fn read_pwm_enable(&self, index: u8) -> Result<String> {
+        ensure_single_fan(index)?;
+        std::fs::read_to_string(&self.fan_path)?
+            .lines()
+            .find_map(|line| line.strip_prefix("level:"))
+            .map(|level| level.trim().to_string())
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("No fan level found on `{}`", self.fan_path.display()),
+                )
+            })
+    }
+
+    fn restore_pwm_enable(&self, index: u8, value: &str) -> Result<()> {
+        ensure_single_fan(index)?;
+        self.write_level(value)
+    }
+
+    /// Numeric levels are manual, as opposed to `auto` and
+    /// `full-speed`.
+    fn is_manual_pwm_enable(&self, value: &str) -> bool {
+        value.trim().parse::<u32>().is_ok()
+    }
+
+    fn read_temp(&self, index: u8) -> Result<TempCelsius> {
+        self.device.read_temp(index)
+    }
+
+    fn read_fan(&self, index: u8) -> Result<Rpm> {
+        self.device.read_fan(index)
+    }
+
+    fn find_temp_by_label(&self, label: &str) -> Result<u8> {
+        self.device.find_temp_by_label(label)
+    }
+
+    fn temp_input_path(&self, index: u8) -> PathBuf {
+        self.device.temp_input_path(index)
+    }
+
+    fn fan_input_path(&self, index: u8) -> PathBuf {
+        self.device.fan_input_path(index)
+    }
+
+    fn pwm_path(&self, _index: u8) -> PathBuf {
+        self.fan_path.clone()
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Percent;
+    use crate::util::TestDir;
+    use std::convert::TryFrom;
+
+    /// Creates a directory laid out as expected by `from_path`, with
+    /// the given value of the `fan_control` parameter.
+    fn fake_thinkpad(name: &str, fan_control: &str) -> TestDir {
+        let dir = TestDir::new(&format!("thinkpad-{}", name));
+        std::fs::write(
+            dir.join("fan"),
+            "status:\t\tenabled\nspeed:\t\t2870\nlevel:\t\tauto\n",
+        )
+        .unwrap();
+        dir.write_attr("fan_control", fan_control);
+        dir.write_attr("fan1_input", "2870");
+        dir
+    }
+
+    fn percent(value: i32) -> PwmMode {
+        PwmMode::ManualPercent(Percent::try_from(value).unwrap())
+    }
+
+    #[test]
+    fn levels() {
+        let dir = fake_thinkpad("levels", "Y");
+        let device = Builder.from_path("laptop".into(), dir.path().to_path_buf(), false);
+        let written = || std::fs::read_to_string(dir.join("fan")).unwrap();
+
+        assert_eq!(device.read_pwm_enable(1).unwrap(), "auto");
+        assert!(!device.is_manual_pwm_enable("auto"));
+        assert!(device.is_manual_pwm_enable("3"));
+        assert_eq!(device.read_fan(1).unwrap().rpm(), 2870);
+
+        device.write_pwm(1, percent(0)).unwrap();
+        assert_eq!(written(), "level 0\n");
+        device.write_pwm(1, percent(1)).unwrap();
+        assert_eq!(written(), "level 1\n");
+        device.write_pwm(1, percent(50)).unwrap();
+        assert_eq!(written(), "level 4\n");
+        device.write_pwm(1, percent(100)).unwrap();
+        assert_eq!(written(), "level 7\n");
+        device.write_pwm(1, PwmMode::Full).unwrap();
+        assert_eq!(written(), "level full-speed\n");
+        device.write_pwm(1, PwmMode::Auto).unwrap();
+        assert_eq!(written(), "level auto\n");
+
+        assert!(device.write_pwm(2, PwmMode::Auto).is_err());
+    }
+
+    #[test]
+    fn fan_control_disabled() {
+        let dir = fake_thinkpad("disabled", "N");
+        let device = Builder.from_path("laptop".into(), dir.path().to_path_buf(), false);
+
+        let err = device.write_pwm(1, PwmMode::Full).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert!(err.to_string().contains("fan_control=1"));
+    }
+}
@@ -1,8 +1,10 @@
 mod dev;
+mod discovery;
 pub mod drivers;
 mod registry;
 mod udevutil;
 
 pub use dev::*;
+pub use discovery::*;
 pub use registry::driver_registry_find;
 pub use udevutil::*;
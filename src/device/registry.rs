@@ -19,7 +19,12 @@ macro_rules! driver_registry {
 
 driver_registry! {
     ("nct6775" . drivers::nct6775::Builder {}),
-    ("hwmon" . drivers::hwmon::Builder {})
+    ("hwmon" . drivers::hwmon::Builder {}),
+    ("amdgpu" . drivers::amdgpu::Builder {}),
+    ("thinkpad" . drivers::thinkpad::Builder {}),
+    ("it87" . drivers::it87::Builder {}),
+    ("thermal_zone" . drivers::thermal_zone::Builder {}),
+    ("mock" . drivers::mock::Builder {})
 }
 
 pub fn driver_registry_find(name: &str) -> Option<&Box<dyn DeviceBuilder + Sync>> {
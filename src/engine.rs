@@ -0,0 +1,863 @@
+//! Evaluation of the rules of a thermal program. The engine only deals
+//! with sensor values and the values the outputs should take, and
+//! knows nothing about where the values come from or where they go,
+//! so the same pipeline drives both the actual devices and the
+//! simulations.
+
+use crate::config::{
+    ast, checker::model as cmodel, model::When, RuleSensor, SymbolOutput, SymbolSensor,
+};
+use crate::types::{Percent, SensorValue};
+use guard::guard;
+use log::debug;
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    hash::Hash,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+/// Result of reading a sensor. The error is reference counted, so the
+/// result can be cached and returned multiple times.
+pub type SensorResult = Result<SensorValue, Rc<std::io::Error>>;
+
+/// Result of combining multiple ComputedRule's with a fold. Have a
+/// different signature from ComputedRule, to keep the final results
+/// for each output traceable from their origin rules.
+#[derive(new)]
+pub struct CombinedRule<'prog> {
+    pub output_values: HashMap<ComputedRuleOutputKey<'prog>, CombinedRuleOutputValue<'prog>>,
+}
+
+impl<'prog> Default for CombinedRule<'prog> {
+    fn default() -> Self {
+        CombinedRule::new(HashMap::new())
+    }
+}
+
+#[derive(new)]
+pub struct CombinedRuleOutputValue<'prog> {
+    pub rule: &'prog When,
+    pub value: Percent,
+}
+
+/// Result of taking a single rule and and computing its actions based
+/// on the configuration of the rule and the inputs of the sensors.
+#[derive(Debug, new)]
+pub struct ComputedRule<'prog> {
+    pub rule: &'prog When,
+    pub sensor_value: SensorValue,
+    pub output_values: HashMap<ComputedRuleOutputKey<'prog>, Percent>,
+    pub should_log: bool,
+}
+
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy)]
+pub struct ComputedRuleOutputKey<'prog> {
+    pub output: &'prog SymbolOutput,
+}
+
+impl<'prog> Hash for ComputedRuleOutputKey<'prog> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::ptr::hash(self.output, state);
+    }
+}
+
+impl<'prog> PartialEq for ComputedRuleOutputKey<'prog> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.output, other.output)
+    }
+}
+
+impl<'prog> Eq for ComputedRuleOutputKey<'prog> {}
+
+impl<'prog> From<&'prog SymbolOutput> for ComputedRuleOutputKey<'prog> {
+    fn from(symbol: &'prog SymbolOutput) -> Self {
+        Self { output: symbol }
+    }
+}
+
+/// Outcome of evaluating the rules of a program once.
+pub struct Evaluation<'prog> {
+    /// The rules that were triggered, along with the values they
+    /// compute for their outputs.
+    pub applying_rules: Vec<ComputedRule<'prog>>,
+
+    /// The final value of each output, after applying the
+    /// priorization of the outputs.
+    pub combined: CombinedRule<'prog>,
+
+    /// Outputs that cannot be safely updated on this evaluation,
+    /// along with the reason.
+    pub failed_outputs: HashMap<ComputedRuleOutputKey<'prog>, String>,
+
+    /// Rules that couldn't be evaluated because their sensor couldn't
+    /// be read, along with the reason. Includes the rules with no
+    /// outputs, which only log.
+    pub failed_rules: Vec<(&'prog When, String)>,
+
+    /// Values read from the sensors of the evaluated rules, indexed
+    /// by sensor name. Includes the aggregate sensors, along with the
+    /// sensors they are computed from.
+    pub readings: HashMap<&'prog str, SensorResult>,
+}
+
+/// Reads the sensor of a rule, computing its value from the sensors
+/// it aggregates, if any. Every reading is cached, so the sensors
+/// shared by several rules or aggregates are read only once.
+fn read_rule_sensor<'prog, F>(
+    sensor: &'prog RuleSensor,
+    readings: &mut HashMap<&'prog str, SensorResult>,
+    read_sensor: &mut F,
+) -> SensorResult
+where
+    F: FnMut(&'prog SymbolSensor) -> SensorResult,
+{
+    if let Some(reading) = readings.get(sensor.name()) {
+        return reading.clone();
+    }
+
+    let reading = match sensor {
+        RuleSensor::Sensor(sensor) => read_sensor(sensor.as_ref()),
+        RuleSensor::Aggregate(aggregate) => aggregate
+            .inputs
+            .iter()
+            .map(|(input, _)| {
+                readings
+                    .entry(input.name.as_str())
+                    .or_insert_with(|| read_sensor(input.as_ref()))
+                    .clone()
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(|values| aggregate.compute(&values)),
+    };
+
+    readings.insert(sensor.name(), reading.clone());
+    reading
+}
+
+/// Checks whether the condition of the rule holds. If the rule was
+/// already triggered on the previous evaluation, the range of the
+/// condition is widened by the hysteresis of the rule, so it keeps
+/// triggered until the sensor leaves the range by more than that
+/// amount.
+fn is_triggered(when: &When, sensor_value: SensorValue, was_triggered: bool) -> bool {
+    let margin = if was_triggered { when.hysteresis } else { 0 };
+
+    match &when.behavior {
+        cmodel::WhenBehavior::Unbounded(rule) => match rule.condition {
+            cmodel::WhenUnboundedCond::Greater(lo) => sensor_value > lo.offset(-margin),
+            cmodel::WhenUnboundedCond::Less(hi) => sensor_value < hi.offset(margin),
+        },
+        cmodel::WhenBehavior::Bounded(rule) => {
+            sensor_value >= rule.cond_min_value.offset(-margin)
+                && sensor_value <= rule.cond_max_value.offset(margin)
+        }
+    }
+}
+
+pub fn compute_rule_actions(when: &When, sensor_value: SensorValue) -> ComputedRule {
+    let mut computed = ComputedRule::new(when, sensor_value, HashMap::new(), false);
+
+    for action in when.iter_actions() {
+        match action {
+            cmodel::AnyAction::Log => {
+                computed.should_log = true;
+            }
+            cmodel::AnyAction::BoundedOutputSet {
+                behavior,
+                target,
+                min,
+                max,
+            } => {
+                // TODO Can this calculation be improved?
+                let min = min.value() as f64;
+                let max = max.value() as f64;
+
+                let sensor_value = sensor_value.raw() as f64;
+                let maxval = behavior.cond_max_value.raw() as f64;
+                let minval = behavior.cond_min_value.raw() as f64;
+                // The sensor value may be out of the range of the
+                // condition if the rule is kept triggered by its
+                // hysteresis.
+                let progress = ((sensor_value - minval) / (maxval - minval))
+                    .max(0.0)
+                    .min(1.0);
+
+                let output_per =
+                    Percent::try_from((min + (progress * (max - min))) as i32).unwrap();
+
+                // TODO Same key shouldn't exist already on the
+                // map. This must be checked on the semantic
+                // analysis of the config.
+                computed
+                    .output_values
+                    .insert(target.as_ref().into(), output_per);
+            }
+            cmodel::AnyAction::FixedOutputSet { target, value } => {
+                computed.output_values.insert(target.as_ref().into(), value);
+            }
+            cmodel::AnyAction::CurveOutputSet { target, curve } => {
+                computed
+                    .output_values
+                    .insert(target.as_ref().into(), curve.value_at(sensor_value));
+            }
+        }
+    }
+
+    computed
+}
+
+enum ValueDiff<V1, V2> {
+    Left(V1),
+    Right(V2),
+    Both(V1, V2),
+}
+
+fn diff_maps_into<K: Hash + Eq, V1, V2>(
+    left: HashMap<K, V1>,
+    mut right: HashMap<K, V2>,
+) -> HashMap<K, ValueDiff<V1, V2>> {
+    let mut result = HashMap::new();
+
+    for (lkey, lvalue) in left.into_iter() {
+        match right.remove_entry(&lkey) {
+            Some((_, rvalue)) => {
+                result.insert(lkey, ValueDiff::Both(lvalue, rvalue));
+            }
+            None => {
+                result.insert(lkey, ValueDiff::Left(lvalue));
+            }
+        }
+    }
+
+    for (rkey, rvalue) in right.into_iter() {
+        result.insert(rkey, ValueDiff::Right(rvalue));
+    }
+
+    result
+}
+
+fn priorization_fun<'prog>(
+    pri: ast::OutputPriorization,
+) -> fn(
+    CombinedRuleOutputValue<'prog>,
+    CombinedRuleOutputValue<'prog>,
+) -> CombinedRuleOutputValue<'prog> {
+    match pri {
+        ast::OutputPriorization::Latest => |_, r| r,
+        ast::OutputPriorization::Min => |l, r| {
+            if l.value > r.value {
+                r
+            } else {
+                l
+            }
+        },
+        ast::OutputPriorization::Max => |l, r| {
+            if l.value > r.value {
+                l
+            } else {
+                r
+            }
+        },
+    }
+}
+
+/// Combine rules attending to the priorization rules specified in the
+/// configuration.
+pub fn combine_rules<'prog>(applying_rules: &[ComputedRule<'prog>]) -> CombinedRule<'prog> {
+    applying_rules
+        .iter()
+        .fold(CombinedRule::default(), |acc, rule| {
+            let output_values = rule.output_values.clone();
+            let source_rule = rule.rule;
+
+            let combined_rule = diff_maps_into(acc.output_values, output_values)
+                .into_iter()
+                .map(|(k, v)| {
+                    let newval = match v {
+                        ValueDiff::Left(value) => value,
+                        ValueDiff::Right(value) => CombinedRuleOutputValue::new(source_rule, value),
+                        ValueDiff::Both(lvalue, rvalue) => {
+                            let rvalue = CombinedRuleOutputValue::new(source_rule, rvalue);
+                            priorization_fun(k.output.priorization.clone())(lvalue, rvalue)
+                        }
+                    };
+                    (k, newval)
+                })
+                .collect();
+
+            CombinedRule::new(combined_rule)
+        })
+}
+
+/// Evaluates the rules of a program, keeping the state required
+/// between evaluations.
+#[derive(Debug, Default)]
+pub struct RuleEngine {
+    /// Indexes of the rules that were triggered on the last
+    /// evaluation, required for applying their hysteresis.
+    triggered_rules: HashSet<u32>,
+
+    /// Time since the condition of each non triggered rule has been
+    /// holding, for the rules with a FOR clause.
+    holding_since: HashMap<u32, Instant>,
+
+    /// Time since the condition of each triggered rule stopped
+    /// holding, for the rules with a RELEASE AFTER clause.
+    releasing_since: HashMap<u32, Instant>,
+}
+
+impl RuleEngine {
+    pub fn is_rule_triggered(&self, rule_index: u32) -> bool {
+        self.triggered_rules.contains(&rule_index)
+    }
+
+    /// Forgets the state of the previous evaluations. Required when
+    /// the evaluated program changes.
+    pub fn reset(&mut self) {
+        self.triggered_rules.clear();
+        self.holding_since.clear();
+        self.releasing_since.clear();
+    }
+
+    /// Evaluates whether the given rule is triggered, and keeps track
+    /// of the result for the next evaluation of the same rule. A rule
+    /// is only triggered once its condition has been holding for the
+    /// hold time of the rule, and is only released once its condition
+    /// has not been holding for the release time of the rule.
+    fn evaluate_rule_trigger(
+        &mut self,
+        when: &When,
+        sensor_value: SensorValue,
+        now: Instant,
+    ) -> bool {
+        let rule_index = when.rule_index;
+        let was_triggered = self.triggered_rules.contains(&rule_index);
+        let holds = is_triggered(when, sensor_value, was_triggered);
+
+        let triggered = if was_triggered {
+            if holds {
+                self.releasing_since.remove(&rule_index);
+                true
+            } else {
+                let since = *self.releasing_since.entry(rule_index).or_insert(now);
+                now.duration_since(since) < when.release
+            }
+        } else if holds {
+            let since = *self.holding_since.entry(rule_index).or_insert(now);
+            now.duration_since(since) >= when.hold
+        } else {
+            self.holding_since.remove(&rule_index);
+            false
+        };
+
+        if triggered != was_triggered {
+            self.holding_since.remove(&rule_index);
+            self.releasing_since.remove(&rule_index);
+        }
+
+        if triggered && !was_triggered {
+            rule_debug!(@ when.rule_name(); "Rule triggered.");
+            self.triggered_rules.insert(rule_index);
+        } else if !triggered && was_triggered {
+            rule_debug!(@ when.rule_name(); "Rule released.");
+            self.triggered_rules.remove(&rule_index);
+        }
+
+        triggered
+    }
+
+    /// Evaluates the given rules at the given time, getting the value
+    /// of their sensors from `read_sensor`. Each sensor is read, and
+    /// each aggregate computed, at most once.
+    pub fn evaluate<'prog, I, F>(
+        &mut self,
+        rules: I,
+        now: Instant,
+        mut read_sensor: F,
+    ) -> Evaluation<'prog>
+    where
+        I: IntoIterator<Item = &'prog When>,
+        F: FnMut(&'prog SymbolSensor) -> SensorResult,
+    {
+        let mut readings: HashMap<&'prog str, SensorResult> = HashMap::new();
+        let mut applying_rules = Vec::new();
+        let mut failed_outputs = HashMap::new();
+        let mut failed_rules = Vec::new();
+
+        for rule in rules {
+            let reading = read_rule_sensor(&rule.sensor, &mut readings, &mut read_sensor);
+
+            match reading {
+                Ok(sensor_value) => {
+                    if self.evaluate_rule_trigger(rule, sensor_value, now) {
+                        applying_rules.push(compute_rule_actions(rule, sensor_value));
+                    }
+                }
+                Err(err) => {
+                    // The rule cannot be evaluated, so the value of
+                    // its outputs is unknown.
+                    let reason = format!("Cannot read sensor `{}`: {}", rule.sensor.name(), err);
+                    for target in rule.iter_targets() {
+                        failed_outputs
+                            .entry(target.as_ref().into())
+                            .or_insert_with(|| reason.clone());
+                    }
+                    failed_rules.push((rule, reason));
+                }
+            }
+        }
+
+        let combined = combine_rules(&applying_rules);
+        Evaluation {
+            applying_rules,
+            combined,
+            failed_outputs,
+            failed_rules,
+            readings,
+        }
+    }
+}
+
+/// Limits the change of the value of the output towards the given
+/// target, according to its ramp rates, assuming the last value was
+/// written an interval ago. The value always changes by at least one
+/// point, so rates slower than a point per interval still make
+/// progress, and jumps over the values below the lowest one the
+/// output runs at.
+fn ramped_value(
+    output: &SymbolOutput,
+    target: Percent,
+    last: Option<Percent>,
+    interval: Duration,
+) -> Percent {
+    guard!(let Some(last) = last else {
+        return target;
+    });
+
+    let (last, target_value) = (last.value() as f64, target.value() as f64);
+    let rate = if target_value > last {
+        output.ramp_up
+    } else {
+        output.ramp_down
+    };
+    guard!(let Some(rate) = rate else {
+        return target;
+    });
+
+    let max_step = (rate as f64 * interval.as_secs_f64()).max(1.0);
+    let value = if target_value > last {
+        (last + max_step).min(target_value)
+    } else {
+        (last - max_step).max(target_value)
+    };
+
+    let mut value = value.round();
+    if let Some(lowest) = output.limits.lowest_running() {
+        let lowest = lowest.value() as f64;
+        if value > 0.0 && value < lowest {
+            value = if target_value == 0.0 { 0.0 } else { lowest };
+        }
+    }
+
+    let value = Percent::try_from(value as i32).unwrap();
+    if value != target {
+        debug!(
+            "Ramping `{}` to {} on its way to {}.",
+            output.name, value, target
+        );
+    }
+    value
+}
+
+/// Turns the values the rules set on the outputs into the values
+/// written to them, applying the limits, ramps and spin up of each
+/// output. Keeps the state required between evaluations, so the same
+/// steps are applied to the actual devices and to the simulations.
+#[derive(Debug, Default)]
+pub struct OutputShaper {
+    /// Outputs being kicked to their spin up value after starting from
+    /// standstill, along with when they started, indexed by name.
+    spinning_up: HashMap<String, Instant>,
+}
+
+impl OutputShaper {
+    /// Computes the value to write to the output, given the value set
+    /// by the rules and the last value written to it, an interval ago.
+    pub fn shape(
+        &mut self,
+        output: &SymbolOutput,
+        target: Percent,
+        last: Option<Percent>,
+        interval: Duration,
+        now: Instant,
+    ) -> Percent {
+        let (limited, limit) = output.limits.apply(target);
+        if let Some(limit) = limit {
+            debug!(
+                "Limited `{}` from {} to {} by its {}.",
+                output.name, target, limited, limit
+            );
+        }
+
+        let ramped = ramped_value(output, limited, last, interval);
+        self.spun_up_value(output, ramped, last, now)
+    }
+
+    /// Raises the value of the output to its spin up value, if it has
+    /// one, for a while after starting from standstill.
+    fn spun_up_value(
+        &mut self,
+        output: &SymbolOutput,
+        value: Percent,
+        last: Option<Percent>,
+        now: Instant,
+    ) -> Percent {
+        guard!(let Some((spin_up, duration)) = output.limits.spin_up else {
+            return value;
+        });
+
+        if value.value() == 0 {
+            self.spinning_up.remove(&output.name);
+            return value;
+        }
+
+        let stopped = last.map_or(true, |last| last.value() == 0);
+        let since = if stopped {
+            self.spinning_up.insert(output.name.clone(), now);
+            now
+        } else {
+            match self.spinning_up.get(&output.name) {
+                Some(&since) => since,
+                None => return value,
+            }
+        };
+
+        if now.duration_since(since) >= duration {
+            self.spinning_up.remove(&output.name);
+            return value;
+        }
+
+        if value < spin_up {
+            debug!(
+                "Spinning up `{}` at {} instead of {}.",
+                output.name, spin_up, value
+            );
+            spin_up
+        } else {
+            value
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::model::ThermalProgram;
+    use crate::types::TempCelsius;
+
+    /// Compiles the given rules, along with a device with a TERMISTOR
+    /// sensor `temp` and a PWM output `fan`.
+    fn program(rules: &str) -> ThermalProgram {
+        program_with_output("", rules)
+    }
+
+    /// Same as `program`, with the given clauses after the definition
+    /// of the output `fan`.
+    fn program_with_output(clauses: &str, rules: &str) -> ThermalProgram {
+        let source = format!(
+            "DEFINE DEVICE `dev` UDEV TAG \"dev\" DRIVER \"hwmon\";\n\
+             DEFINE SENSOR `temp` DEVICE `dev` TYPE TERMISTOR INDEX 1;\n\
+             DEFINE OUTPUT `fan` DEVICE `dev` TYPE PWM INDEX 1 {};\n\
+             {}",
+            clauses, rules
+        );
+        crate::config::compile("test.conf", &source).unwrap().0
+    }
+
+    fn output(program: &ThermalProgram) -> &SymbolOutput {
+        program
+            .symbol_table
+            .get_all_symbols_of_type::<SymbolOutput>()[0]
+    }
+
+    fn percent(value: i32) -> Percent {
+        Percent::try_from(value).unwrap()
+    }
+
+    fn mcelsius(value: i32) -> SensorValue {
+        SensorValue::Temp(TempCelsius::from_mcelsius(value))
+    }
+
+    #[test]
+    fn hysteresis_greater() {
+        let program = program("WHEN `temp` > 50 HYSTERESIS 5 DO SET `fan` TO 100%; END");
+        let when = &program.rules[0];
+
+        assert!(!is_triggered(when, mcelsius(50_000), false));
+        assert!(is_triggered(when, mcelsius(50_001), false));
+        assert!(!is_triggered(when, mcelsius(46_000), false));
+
+        assert!(is_triggered(when, mcelsius(46_000), true));
+        assert!(is_triggered(when, mcelsius(45_001), true));
+        assert!(!is_triggered(when, mcelsius(45_000), true));
+    }
+
+    #[test]
+    fn hysteresis_less() {
+        let program = program("WHEN `temp` < 30 HYSTERESIS 5 DO SET `fan` TO 20%; END");
+        let when = &program.rules[0];
+
+        assert!(!is_triggered(when, mcelsius(30_000), false));
+        assert!(is_triggered(when, mcelsius(29_999), false));
+        assert!(!is_triggered(when, mcelsius(34_000), false));
+
+        assert!(is_triggered(when, mcelsius(34_000), true));
+        assert!(is_triggered(when, mcelsius(34_999), true));
+        assert!(!is_triggered(when, mcelsius(35_000), true));
+    }
+
+    #[test]
+    fn hysteresis_between() {
+        let program =
+            program("WHEN `temp` BETWEEN 40 AND 60 HYSTERESIS 5 DO SET `fan` TO 50%; END");
+        let when = &program.rules[0];
+
+        assert!(is_triggered(when, mcelsius(40_000), false));
+        assert!(is_triggered(when, mcelsius(60_000), false));
+        assert!(!is_triggered(when, mcelsius(39_999), false));
+        assert!(!is_triggered(when, mcelsius(60_001), false));
+
+        assert!(is_triggered(when, mcelsius(35_000), true));
+        assert!(is_triggered(when, mcelsius(65_000), true));
+        assert!(!is_triggered(when, mcelsius(34_999), true));
+        assert!(!is_triggered(when, mcelsius(65_001), true));
+    }
+
+    /// Rule that triggers above 50 °C after holding for 10 seconds,
+    /// and is released 5 seconds after not holding.
+    const TIMED_RULE: &str = "WHEN `temp` > 50 FOR 10s RELEASE AFTER 5s DO SET `fan` TO 100%; END";
+
+    /// Evaluates the trigger of the rule on each of the given seconds
+    /// since the start, with the given temperature in °C.
+    fn triggers(
+        engine: &mut RuleEngine,
+        when: &When,
+        start: Instant,
+        steps: &[(u64, i32)],
+    ) -> Vec<bool> {
+        steps
+            .iter()
+            .map(|&(secs, celsius)| {
+                let now = start + Duration::from_secs(secs);
+                engine.evaluate_rule_trigger(when, mcelsius(celsius * 1000), now)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hold_before_triggering() {
+        let program = program(TIMED_RULE);
+        let mut engine = RuleEngine::default();
+        let start = Instant::now();
+
+        assert_eq!(
+            triggers(
+                &mut engine,
+                &program.rules[0],
+                start,
+                &[(0, 60), (5, 60), (9, 60), (10, 60)]
+            ),
+            [false, false, false, true]
+        );
+    }
+
+    #[test]
+    fn hold_reset_when_condition_drops() {
+        let program = program(TIMED_RULE);
+        let mut engine = RuleEngine::default();
+        let start = Instant::now();
+
+        assert_eq!(
+            triggers(
+                &mut engine,
+                &program.rules[0],
+                start,
+                &[(0, 60), (5, 40), (8, 60), (10, 60), (17, 60), (18, 60)]
+            ),
+            [false, false, false, false, false, true]
+        );
+    }
+
+    #[test]
+    fn release_after() {
+        let program = program(TIMED_RULE);
+        let mut engine = RuleEngine::default();
+        let start = Instant::now();
+
+        assert_eq!(
+            triggers(
+                &mut engine,
+                &program.rules[0],
+                start,
+                &[
+                    (0, 60),
+                    (10, 60),
+                    (11, 40),
+                    (14, 40),
+                    (15, 60),
+                    (16, 40),
+                    (20, 40),
+                    (21, 40)
+                ]
+            ),
+            [false, true, true, true, true, true, true, false]
+        );
+    }
+
+    #[test]
+    fn hold_again_after_release() {
+        let program = program(TIMED_RULE);
+        let mut engine = RuleEngine::default();
+        let start = Instant::now();
+
+        assert_eq!(
+            triggers(
+                &mut engine,
+                &program.rules[0],
+                start,
+                &[
+                    (0, 60),
+                    (10, 60),
+                    (11, 40),
+                    (16, 40),
+                    (17, 60),
+                    (26, 60),
+                    (27, 60)
+                ]
+            ),
+            [false, true, true, false, false, false, true]
+        );
+    }
+
+    #[test]
+    fn ramp_up_and_down() {
+        let program = program_with_output("RAMP UP 10% PER SECOND RAMP DOWN 5% PER SECOND", "");
+        let fan = output(&program);
+        let second = Duration::from_secs(1);
+
+        assert_eq!(
+            ramped_value(fan, percent(80), Some(percent(20)), second),
+            percent(30)
+        );
+        assert_eq!(
+            ramped_value(fan, percent(80), Some(percent(20)), 2 * second),
+            percent(40)
+        );
+        assert_eq!(
+            ramped_value(fan, percent(20), Some(percent(80)), second),
+            percent(75)
+        );
+        assert_eq!(
+            ramped_value(fan, percent(20), Some(percent(80)), second / 2),
+            percent(78)
+        );
+    }
+
+    #[test]
+    fn ramp_clamped_at_target() {
+        let program = program_with_output("RAMP UP 10% PER SECOND RAMP DOWN 5% PER SECOND", "");
+        let fan = output(&program);
+        let second = Duration::from_secs(1);
+
+        assert_eq!(
+            ramped_value(fan, percent(80), Some(percent(75)), second),
+            percent(80)
+        );
+        assert_eq!(
+            ramped_value(fan, percent(20), Some(percent(22)), second),
+            percent(20)
+        );
+        assert_eq!(
+            ramped_value(fan, percent(50), Some(percent(50)), second),
+            percent(50)
+        );
+    }
+
+    #[test]
+    fn ramp_zero_interval() {
+        let program = program_with_output("RAMP UP 10% PER SECOND RAMP DOWN 5% PER SECOND", "");
+        let fan = output(&program);
+
+        assert_eq!(
+            ramped_value(fan, percent(80), Some(percent(20)), Duration::ZERO),
+            percent(21)
+        );
+        assert_eq!(
+            ramped_value(fan, percent(20), Some(percent(80)), Duration::ZERO),
+            percent(79)
+        );
+    }
+
+    #[test]
+    fn ramp_first_write() {
+        let program = program_with_output("RAMP UP 10% PER SECOND", "");
+        let fan = output(&program);
+
+        assert_eq!(
+            ramped_value(fan, percent(80), None, Duration::from_secs(1)),
+            percent(80)
+        );
+        assert_eq!(
+            ramped_value(fan, percent(20), Some(percent(80)), Duration::from_secs(1)),
+            percent(20)
+        );
+    }
+
+    /// Shapes the given values set by the rules, one per second,
+    /// writing each shaped value to the output before the next one.
+    fn shape_each_second(output: &SymbolOutput, targets: &[i32]) -> Vec<Percent> {
+        let mut shaper = OutputShaper::default();
+        let start = Instant::now();
+        let interval = Duration::from_secs(1);
+        let mut last = None;
+
+        (0..)
+            .zip(targets.iter())
+            .map(|(secs, &target)| {
+                let now = start + interval * secs;
+                let value = shaper.shape(output, percent(target), last, interval, now);
+                last = Some(value);
+                value
+            })
+            .collect()
+    }
+
+    fn percents(values: &[i32]) -> Vec<Percent> {
+        values.iter().map(|&value| percent(value)).collect()
+    }
+
+    #[test]
+    fn spin_up_and_stop_below() {
+        let program = program_with_output("MIN 20% STOP BELOW 15% START AT 60% FOR 2s", "");
+        let fan = output(&program);
+
+        assert_eq!(
+            shape_each_second(fan, &[30, 30, 30, 10, 17, 70, 40, 40]),
+            percents(&[60, 60, 30, 0, 60, 70, 40, 40])
+        );
+    }
+
+    #[test]
+    fn spin_up_after_stopping() {
+        let program = program_with_output("STOP BELOW 15% START AT 50% FOR 3s", "");
+        let fan = output(&program);
+
+        assert_eq!(
+            shape_each_second(fan, &[0, 20, 20, 10, 20, 20, 20, 20]),
+            percents(&[0, 50, 50, 0, 50, 50, 50, 20])
+        );
+    }
+}
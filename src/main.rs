@@ -4,7 +4,7 @@
 #[macro_use]
 extern crate derive_new;
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use env_logger::fmt::Color;
 use guard::guard;
 use log::{error, info, warn};
@@ -13,58 +13,100 @@ use std::ops::DerefMut;
 use std::rc::Rc;
 use std::{
     convert::TryFrom,
-    hash::Hash,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
 use targeted_log::targeted_log;
-use types::{Percent, TempCelsius};
+use types::{Percent, SensorValue};
 use udevpoll::{PollMode, UdevPoller};
 
+// Defined before the modules, so they can log on behalf of the rules.
+targeted_log!("config::rule {}", rule_);
+targeted_log!("udev_events", devev_);
+
+mod check;
 mod config;
+mod control;
 mod device;
+mod engine;
+mod metrics;
+mod record;
+mod signals;
+mod simulate;
 mod types;
 mod udevpoll;
 mod util;
 
-use std::{cell::RefCell, collections::HashMap, error::Error, io::Write};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    error::Error,
+    io::Write,
+    net::SocketAddr,
+    path::PathBuf,
+    process::Command,
+};
 
 use config::{
-    ast, checker::model as cmodel, model::When, SymbolDevice, SymbolOutput, SymbolSensor,
+    ast, checker::model as cmodel, model::When, SensorSelector, SymbolDevice, SymbolOutput,
+    SymbolSensor,
+};
+use device::{
+    driver_registry_find, udev_extract_tags, Device, DeviceDiscovery, DeviceLocation, PwmMode,
 };
-use device::{driver_registry_find, udev_extract_tags, udev_find_with_tags, Device, PwmMode};
+use engine::{ComputedRuleOutputKey, OutputShaper, RuleEngine, SensorResult};
 use udev::{Device as UdevDevice, Event, MonitorBuilder};
 
 #[macro_use]
 extern crate lalrpop_util;
 
-targeted_log!("config::rule {}", rule_);
-targeted_log!("udev_events", devev_);
 
 const EXIT_CODE_GENERAL_ERROR: i32 = 1;
 const EXIT_CODE_HOT_UNPLUG: i32 = 2;
 
 const UDEV_FANCONTROL_TAG: &str = "fancontrol";
 
-fn create_device(driver: &str, name: String, device: UdevDevice, dryrun: bool) -> Box<dyn Device> {
+/// Time between each check of the device directory while waiting for
+/// devices to show up, when devices are discovered from a directory.
+const DEVICE_DIRECTORY_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Number of consecutive failed evaluations of a rule between each
+/// warning about it, after the first one.
+const RULE_FAILURE_WARN_EVERY: u32 = 60;
+
+fn create_device(
+    driver: &str,
+    name: String,
+    location: DeviceLocation,
+    dryrun: bool,
+) -> Box<dyn Device> {
     // FIXME Unwrap
     let builder = driver_registry_find(driver).unwrap();
-    builder.from_udev(name, device, dryrun)
+    match location {
+        DeviceLocation::Udev(device) => builder.from_udev(name, device, dryrun),
+        DeviceLocation::Directory(path) => builder.from_path(name, path, dryrun),
+    }
 }
 
-fn create_online_device_for_symbol<'prog>(
-    context: &RunContext<'prog>,
-    symbol: &'prog Rc<SymbolDevice>,
-    udev_device: UdevDevice,
-) -> OnlineDevice<'prog> {
-    OnlineDevice::new(
+fn create_online_device_for_symbol(
+    context: &RunContext,
+    symbol: Rc<SymbolDevice>,
+    location: DeviceLocation,
+) -> OnlineDevice {
+    let output_indexes = context.output_indexes_of(&symbol);
+    let sensors = context.sensors_of(&symbol);
+    let mut device = OnlineDevice::new(
         create_device(
             &symbol.driver,
             symbol.name.clone(),
-            udev_device,
+            location,
             context.dryrun,
         ),
         symbol,
-    )
+    );
+
+    device.save_pwm_enables(output_indexes.into_iter());
+    device.resolve_sensors(&sensors);
+    device
 }
 
 enum DeviceState {
@@ -94,7 +136,7 @@ fn find_device_state(device: &UdevDevice, context: &mut RunContext) -> DeviceSta
             context
                 .offline_devices
                 .iter()
-                .position(|&offline_device| {
+                .position(|offline_device| {
                     tags.iter()
                         .any(|&device_tag| device_tag == offline_device.tag)
                 })
@@ -146,12 +188,14 @@ fn process_device_event(context: &mut RunContext, event: Event) {
             if adding {
                 // Device is being added, and is now offline, everything's ok.
                 let symbol = context.offline_devices.remove(index);
-                context.online_devices.push(create_online_device_for_symbol(
+                devev_info!("Device {} plugged in at {:?}", symbol.name, event.devpath());
+                context.record_hotplug_event(&symbol.name, "add");
+                let device = create_online_device_for_symbol(
                     &context,
                     symbol,
-                    event.device(),
-                ));
-                devev_info!("Device {} plugged in at {:?}", symbol.name, event.devpath());
+                    DeviceLocation::Udev(event.device()),
+                );
+                context.online_devices.push(device);
             } else {
                 // Device is offline but there's an attempt of removing it again.
                 devev_error!(
@@ -176,8 +220,9 @@ fn process_device_event(context: &mut RunContext, event: Event) {
                 );
             } else {
                 let device = context.online_devices.remove(index);
-                context.offline_devices.push(device.symbol);
                 devev_info!("Device {} unplugged", device.name());
+                context.record_hotplug_event(device.name(), "remove");
+                context.offline_devices.push(device.symbol);
             }
         }
         DeviceState::UnknownDevice => {} // Ignore
@@ -193,178 +238,410 @@ fn poll_device_events(context: &mut RunContext, poller: &mut UdevPoller, mode: P
     }
 }
 
-#[derive(Debug, new)]
-struct OnlineDevice<'prog> {
-    inner: Box<dyn Device>,
-    symbol: &'prog Rc<SymbolDevice>,
-}
-
-impl Deref for OnlineDevice<'_> {
-    type Target = dyn Device;
+/// Looks for changes on the devices when they are discovered from a
+/// directory. A device is considered unplugged when its directory is
+/// removed, and plugged in when it shows up again.
+fn poll_device_directory(context: &mut RunContext) {
+    let mut index = 0;
+    while index < context.online_devices.len() {
+        if context
+            .discovery
+            .find(&context.online_devices[index].symbol.tag)
+            .is_some()
+        {
+            index += 1;
+            continue;
+        }
 
-    fn deref(&self) -> &Self::Target {
-        self.inner.as_ref()
+        let device = context.online_devices.remove(index);
+        devev_info!("Device {} unplugged", device.name());
+        context.record_hotplug_event(device.name(), "remove");
+        context.offline_devices.push(device.symbol);
     }
-}
 
-impl DerefMut for OnlineDevice<'_> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        self.inner.as_mut()
+    let mut index = 0;
+    while index < context.offline_devices.len() {
+        guard!(let Some(location) = context.discovery.find(&context.offline_devices[index].tag) else {
+            index += 1;
+            continue;
+        });
+
+        let symbol = context.offline_devices.remove(index);
+        devev_info!("Device {} plugged in at {:?}", symbol.name, location);
+        context.record_hotplug_event(&symbol.name, "add");
+        let device = create_online_device_for_symbol(&context, symbol, location);
+        context.online_devices.push(device);
     }
 }
 
-#[derive(new, Debug)]
-struct OnlineThermalRule<'prog> {
-    sensor: OnlineSensor<'prog>,
-    when: &'prog cmodel::When,
-}
-
-impl<'prog> OnlineThermalRule<'prog> {
-    pub fn is_triggered(&self) -> bool {
-        let sensor_value = self.sensor.read_cached();
-        match &self.when.behavior {
-            cmodel::WhenBehavior::Unbounded(rule) => match rule.condition {
-                cmodel::WhenUnboundedCond::Greater(lo) => {
-                    sensor_value > TempCelsius::from_celsius(lo)
+/// Processes the devices that have been plugged in or unplugged,
+/// either through udev or, if there is no udev poller, by looking at
+/// the device directory.
+fn poll_devices(context: &mut RunContext, poller: Option<&mut UdevPoller>, mode: PollMode) {
+    match poller {
+        Some(poller) => poll_device_events(context, poller, mode),
+        None => {
+            poll_device_directory(context);
+            match mode {
+                PollMode::NoWait => {}
+                PollMode::WaitInfinite => std::thread::sleep(DEVICE_DIRECTORY_POLL_INTERVAL),
+                PollMode::WaitTimeout(timeout) => {
+                    std::thread::sleep(timeout.min(DEVICE_DIRECTORY_POLL_INTERVAL))
                 }
-                cmodel::WhenUnboundedCond::Less(hi) => sensor_value < TempCelsius::from_celsius(hi),
-            },
-            cmodel::WhenBehavior::Bounded(rule) => {
-                sensor_value >= TempCelsius::from_celsius(rule.cond_min_value)
-                    && sensor_value <= TempCelsius::from_celsius(rule.cond_max_value)
             }
         }
     }
 }
 
-#[derive(Debug)]
-struct OnlineSensor<'prog> {
-    device: &'prog OnlineDevice<'prog>,
-    symbol: &'prog SymbolSensor,
-    _cached_value: RefCell<Option<TempCelsius>>,
+#[derive(Debug, new)]
+struct OnlineDevice {
+    inner: Box<dyn Device>,
+    symbol: Rc<SymbolDevice>,
+
+    /// Values of the `pwmN_enable` attribute of the outputs of the
+    /// device at the time it went online, indexed by output.
+    #[new(default)]
+    saved_pwm_enables: HashMap<u8, String>,
+
+    /// Indexes of the outputs of the device the program has written
+    /// to.
+    #[new(default)]
+    touched_outputs: RefCell<HashSet<u8>>,
+
+    /// Indexes of the sensors of the device selected by name, resolved
+    /// when the device went online, indexed by sensor name.
+    #[new(default)]
+    sensor_indexes: HashMap<String, u8>,
 }
 
-impl<'prog> OnlineSensor<'prog> {
-    fn new(device: &'prog OnlineDevice<'prog>, symbol: &'prog SymbolSensor) -> Self {
-        Self {
-            device,
-            symbol,
-            _cached_value: RefCell::new(None),
+impl OnlineDevice {
+    /// Resolves the index of the given sensors that are selected by
+    /// name. The sensors that cannot be resolved fail when read.
+    fn resolve_sensors(&mut self, sensors: &[Rc<SymbolSensor>]) {
+        self.sensor_indexes.clear();
+        for sensor in sensors {
+            let resolved = match &sensor.selector {
+                SensorSelector::Index(_) => continue,
+                SensorSelector::Zone(zone_type) => self.inner.find_zone(zone_type),
+                SensorSelector::Label(label) => self.inner.find_temp_by_label(label),
+            };
+
+            match resolved {
+                Ok(index) => {
+                    info!(
+                        "Sensor `{}` ({}) of `{}` resolved to index {}.",
+                        sensor.name,
+                        sensor.selector,
+                        self.name(),
+                        index
+                    );
+                    self.sensor_indexes.insert(sensor.name.clone(), index);
+                }
+                Err(err) => error!(
+                    "Cannot resolve sensor `{}` ({}) of `{}`: {}",
+                    sensor.name,
+                    sensor.selector,
+                    self.name(),
+                    err
+                ),
+            }
         }
     }
 
-    fn read(&'prog self) -> TempCelsius {
-        self.device.read_temp(self.symbol.index as u8).unwrap()
+    /// Returns the index of the given sensor of the device.
+    fn sensor_index(&self, sensor: &SymbolSensor) -> std::io::Result<u8> {
+        match &sensor.selector {
+            SensorSelector::Index(index) => Ok(*index),
+            selector => self.sensor_indexes.get(&sensor.name).copied().ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("Sensor {} not found on `{}`", selector, self.name()),
+                )
+            }),
+        }
     }
 
-    fn read_cached(&'prog self) -> TempCelsius {
-        // TODO fix this shit.
-        if let Some(&value) = self._cached_value.borrow().as_ref() {
-            return value;
+    fn save_pwm_enables<I: Iterator<Item = u8>>(&mut self, indexes: I) {
+        for index in indexes {
+            if self.saved_pwm_enables.contains_key(&index) {
+                continue;
+            }
+
+            match self.inner.read_pwm_enable(index) {
+                // The value of a manual output is not saved, so it
+                // would stay at whatever the program wrote last.
+                Ok(value) if self.inner.is_manual_pwm_enable(&value) => info!(
+                    "Output {} of `{}` is already in manual mode. It will be set to \
+                     automatic mode when released.",
+                    index,
+                    self.name()
+                ),
+                Ok(value) => {
+                    self.saved_pwm_enables.insert(index, value);
+                }
+                Err(err) => warn!(
+                    "Cannot read the PWM mode of output {} of `{}`: {}",
+                    index,
+                    self.name(),
+                    err
+                ),
+            }
         }
+    }
 
-        let temp = self.read();
-        self._cached_value.replace(Some(temp));
-        temp
+    fn write_output(&self, index: u8, mode: PwmMode) -> std::io::Result<()> {
+        self.touched_outputs.borrow_mut().insert(index);
+        self.inner.write_pwm(index, mode)
     }
-}
 
-/// Result of combining multiple ComputedRule's with a fold. Have a
-/// different signature from ComputedRule, to keep the final results
-/// for each output traceable from their origin rules.
-#[derive(new)]
-struct CombinedRule<'prog> {
-    output_values: HashMap<ComputedRuleOutputKey<'prog>, CombinedRuleOutputValue<'prog>>,
-}
+    /// Hands back the control of an output to the mode it had when the
+    /// device went online. If it is unknown, was manual, or cannot be
+    /// restored, the output is set to automatic mode or, if the driver
+    /// doesn't support it, to full speed.
+    fn release_output(&self, index: u8) {
+        if let Some(value) = self.saved_pwm_enables.get(&index) {
+            match self.inner.restore_pwm_enable(index, value) {
+                Ok(()) => {
+                    info!(
+                        "Restored output {} of `{}` to its original mode.",
+                        index,
+                        self.name()
+                    );
+                    return;
+                }
+                Err(err) => warn!(
+                    "Cannot restore the original mode of output {} of `{}`: {}",
+                    index,
+                    self.name(),
+                    err
+                ),
+            }
+        }
 
-impl<'prog> Default for CombinedRule<'prog> {
-    fn default() -> Self {
-        CombinedRule::new(HashMap::new())
+        if let Err(err) = self.inner.write_pwm(index, PwmMode::Auto) {
+            warn!(
+                "Cannot set output {} of `{}` to automatic mode: {}. Setting it to full speed.",
+                index,
+                self.name(),
+                err
+            );
+
+            if let Err(err) = self.inner.write_pwm(index, PwmMode::Full) {
+                error!(
+                    "Cannot set output {} of `{}` to full speed: {}",
+                    index,
+                    self.name(),
+                    err
+                );
+            }
+        }
     }
-}
 
-#[derive(new)]
-struct CombinedRuleOutputValue<'prog> {
-    rule: &'prog OnlineThermalRule<'prog>,
-    value: Percent,
-}
+    fn release_outputs(&self) {
+        for &index in self.touched_outputs.borrow().iter() {
+            self.release_output(index);
+        }
+    }
 
-/// Result of taking a single rule and and computing its actions based
-/// on the configuration of the rule and the inputs of the sensors.
-#[derive(Debug, new)]
-struct ComputedRule<'prog> {
-    rule: &'prog OnlineThermalRule<'prog>,
-    output_values: HashMap<ComputedRuleOutputKey<'prog>, Percent>,
-    should_log: bool,
-}
+    /// Binds the device to the definition of a new program, given the
+    /// indexes of the outputs of the device that are used by it. The
+    /// outputs the program no longer uses are released.
+    fn rebind(
+        &mut self,
+        symbol: Rc<SymbolDevice>,
+        output_indexes: HashSet<u8>,
+        sensors: &[Rc<SymbolSensor>],
+    ) {
+        let released_outputs: Vec<u8> = self
+            .touched_outputs
+            .borrow()
+            .difference(&output_indexes)
+            .copied()
+            .collect();
 
-#[repr(transparent)]
-#[derive(Debug)]
-struct ComputedRuleOutputKey<'prog> {
-    output: &'prog SymbolOutput,
-}
+        for index in released_outputs {
+            self.release_output(index);
+            self.touched_outputs.borrow_mut().remove(&index);
+        }
 
-impl<'prog> Hash for ComputedRuleOutputKey<'prog> {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        std::ptr::hash(self.output, state);
+        self.save_pwm_enables(output_indexes.into_iter());
+        self.resolve_sensors(sensors);
+        self.symbol = symbol;
     }
 }
 
-impl<'prog> PartialEq for ComputedRuleOutputKey<'prog> {
-    fn eq(&self, other: &Self) -> bool {
-        std::ptr::eq(self.output, other.output)
+impl Deref for OnlineDevice {
+    type Target = dyn Device;
+
+    fn deref(&self) -> &Self::Target {
+        self.inner.as_ref()
     }
 }
 
-impl<'prog> Eq for ComputedRuleOutputKey<'prog> {}
-
-impl<'prog> From<&'prog SymbolOutput> for ComputedRuleOutputKey<'prog> {
-    fn from(symbol: &'prog SymbolOutput) -> Self {
-        Self { output: symbol }
+impl DerefMut for OnlineDevice {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.inner.as_mut()
     }
 }
 
 #[derive(new, Debug)]
-struct RunContext<'prog> {
-    pub thermal_program: &'prog cmodel::ThermalProgram,
-    pub online_devices: Vec<OnlineDevice<'prog>>,
-    pub offline_devices: Vec<&'prog Rc<SymbolDevice>>,
+struct RunContext {
+    pub thermal_program: Rc<cmodel::ThermalProgram>,
+    pub online_devices: Vec<OnlineDevice>,
+    pub offline_devices: Vec<Rc<SymbolDevice>>,
     pub dryrun: bool,
+    pub discovery: DeviceDiscovery,
+
+    #[new(default)]
+    rule_engine: RefCell<RuleEngine>,
+
+    /// Outputs that failed on the last evaluations, indexed by name.
+    #[new(default)]
+    output_failures: RefCell<HashMap<String, OutputFailure>>,
+
+    /// Number of consecutive evaluations each rule couldn't be
+    /// evaluated because of its sensor, indexed by rule name.
+    #[new(default)]
+    rule_failures: RefCell<HashMap<String, u32>>,
+
+    /// Last value written to each output, indexed by name.
+    #[new(default)]
+    written_outputs: RefCell<HashMap<String, Percent>>,
+
+    /// State of the limits, ramps and spin up of the outputs.
+    #[new(default)]
+    output_shaper: RefCell<OutputShaper>,
+
+    /// Outputs whose tachometer is reading 0 RPM while being
+    /// commanded above the stall threshold, indexed by name.
+    #[new(default)]
+    stalled_outputs: RefCell<HashMap<String, StalledOutput>>,
+
+    /// Outputs manually set to a fixed value through the control
+    /// socket, indexed by name.
+    #[new(default)]
+    overrides: RefCell<HashMap<String, OutputOverride>>,
+
+    /// Outcome of the last evaluation of the rules, as reported
+    /// through the control socket.
+    #[new(default)]
+    last_evaluation: RefCell<control::RulesStatus>,
+
+    /// Values read from the sensors on the last evaluation of the
+    /// rules, as exported through the metrics, indexed by sensor name.
+    #[new(default)]
+    last_readings: RefCell<HashMap<String, SensorValue>>,
+
+    /// Number of failed reads or writes on each device, indexed by
+    /// device name.
+    #[new(default)]
+    io_errors: RefCell<HashMap<String, u64>>,
+
+    /// Number of devices plugged in or unplugged, indexed by device
+    /// name and udev action.
+    #[new(default)]
+    hotplug_events: RefCell<HashMap<(String, &'static str), u64>>,
+}
+
+#[derive(Debug, new)]
+struct OutputOverride {
+    value: Percent,
+    until: Instant,
+}
+
+#[derive(Debug, new)]
+struct StalledOutput {
+    /// Name of the device the output belongs to.
+    device: String,
+    since: Instant,
+    #[new(default)]
+    alarm_raised: bool,
+}
+
+#[derive(Debug, Default)]
+struct OutputFailure {
+    /// Number of consecutive evaluations the output has failed.
+    count: u32,
+    failsafe: bool,
 }
 
 // TODO Create a better interface for adding or dropping new online devices, and
 // recalculating online rules.
-impl<'prog, 'this> RunContext<'prog>
-where
-    'prog: 'this,
-{
-    pub fn find_device(&'this self, name: &str) -> Option<&'this OnlineDevice<'prog>> {
+impl RunContext {
+    pub fn find_device(&self, name: &str) -> Option<&OnlineDevice> {
         self.online_devices
             .iter()
             .find(|device| device.name() == name)
     }
 
-    pub fn find_device_mut(&'this mut self, name: &str) -> Option<&'this mut OnlineDevice<'prog>> {
+    pub fn find_device_mut(&mut self, name: &str) -> Option<&mut OnlineDevice> {
         self.online_devices
             .iter_mut()
             .find(|device| device.name() == name)
     }
 
-    pub fn get_online_rules(&'this self) -> Vec<OnlineThermalRule<'this>> {
+    /// Returns the indexes of the outputs of the given device that are
+    /// used by the running program.
+    pub fn output_indexes_of(&self, device: &SymbolDevice) -> HashSet<u8> {
+        self.thermal_program
+            .symbol_table
+            .get_all_symbols_of_type::<SymbolOutput>()
+            .into_iter()
+            .filter(|output| output.device.name == device.name)
+            .map(|output| output.index)
+            .collect()
+    }
+
+    /// Returns the sensors of the given device that are defined by the
+    /// running program.
+    pub fn sensors_of(&self, device: &SymbolDevice) -> Vec<Rc<SymbolSensor>> {
+        self.thermal_program
+            .symbol_table
+            .get_all_symbols_of_type::<SymbolSensor>()
+            .into_iter()
+            .filter(|sensor| sensor.device.name == device.name)
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the rules whose sensor is located on an online device.
+    /// Aggregate sensors require all the devices of their sensors.
+    pub fn get_online_rules(&self) -> Vec<&When> {
         self.thermal_program
             .rules
             .iter()
-            .filter_map(|rule| match self.find_device(&rule.sensor.device.name) {
-                Some(device) => Some(OnlineThermalRule::new(
-                    OnlineSensor::new(device, &rule.sensor),
-                    rule,
-                )),
-                None => None,
+            .filter(|rule| {
+                rule.sensor
+                    .sources()
+                    .iter()
+                    .all(|sensor| self.find_device(&sensor.device.name).is_some())
             })
             .collect()
     }
 
-    pub fn register_device(&mut self, device: OnlineDevice<'prog>) {
+    /// Reads the current value of the given sensor.
+    pub fn read_sensor(&self, sensor: &SymbolSensor) -> SensorResult {
+        guard!(let Some(device) = self.find_device(&sensor.device.name) else {
+            return Err(Rc::new(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("Device `{}` is offline", sensor.device.name),
+            )));
+        });
+
+        let value = device
+            .sensor_index(sensor)
+            .and_then(|index| match sensor.sensor_type {
+                ast::SensorType::Termistor => device.read_temp(index).map(SensorValue::Temp),
+                ast::SensorType::Fan => device.read_fan(index).map(SensorValue::Fan),
+            });
+
+        value.map_err(|err| {
+            self.record_io_error(&sensor.device.name);
+            Rc::new(err)
+        })
+    }
+
+    pub fn register_device(&mut self, device: OnlineDevice) {
         if let Some(_) = self.find_device(device.name()) {
             panic!("Device already registered: {}", device.name())
         } else {
@@ -372,7 +649,7 @@ where
         }
     }
 
-    fn filter_non_hotpluggable_offline_devices(&self) -> impl Iterator<Item = &&Rc<SymbolDevice>> {
+    fn filter_non_hotpluggable_offline_devices(&self) -> impl Iterator<Item = &Rc<SymbolDevice>> {
         self.offline_devices.iter().filter(|dev| !dev.allow_hotplug)
     }
 
@@ -382,117 +659,803 @@ where
             .is_some()
     }
 
-    pub fn compute_rule_actions(
-        &'this self,
-        online_rule: &'prog OnlineThermalRule,
-    ) -> ComputedRule<'prog> {
-        let when = online_rule.when;
+    /// Replaces the running program with a new one. The online devices
+    /// whose definition didn't change are kept online, while the rest
+    /// of them are released and discovered again. If any of the
+    /// non-hotpluggable devices of the new program cannot be found,
+    /// the running program is left untouched.
+    pub fn replace_program(
+        &mut self,
+        program: Rc<cmodel::ThermalProgram>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut kept_symbols = Vec::new();
+        let mut found_devices = Vec::new();
+        let mut offline_symbols = Vec::new();
+
+        for symbol in program
+            .symbol_table
+            .get_all_symbols_of_type::<SymbolDevice>()
+            .into_iter()
+        {
+            if self
+                .online_devices
+                .iter()
+                .any(|device| device.symbol.same_binding(symbol))
+            {
+                kept_symbols.push(symbol.clone());
+            } else {
+                match self.discovery.find(&symbol.tag) {
+                    Some(location) => found_devices.push((symbol.clone(), location)),
+                    None if symbol.allow_hotplug => offline_symbols.push(symbol.clone()),
+                    None => {
+                        return Err(format!(
+                            "Unable to find required device `{}` attached in the system",
+                            symbol.name
+                        )
+                        .into())
+                    }
+                }
+            }
+        }
 
-        let mut computed = ComputedRule::new(online_rule, HashMap::new(), false);
+        self.thermal_program = program;
+        self.offline_devices = offline_symbols;
+        self.rule_engine.borrow_mut().reset();
+        self.output_failures.borrow_mut().clear();
+        self.rule_failures.borrow_mut().clear();
+        self.stalled_outputs.borrow_mut().clear();
 
-        for action in when.iter_actions() {
-            match action {
-                cmodel::AnyAction::Log => {
-                    computed.should_log = true;
+        for mut device in std::mem::take(&mut self.online_devices).into_iter() {
+            match kept_symbols
+                .iter()
+                .find(|symbol| device.symbol.same_binding(symbol))
+            {
+                Some(symbol) => {
+                    let output_indexes = self.output_indexes_of(symbol);
+                    let sensors = self.sensors_of(symbol);
+                    device.rebind(symbol.clone(), output_indexes, &sensors);
+                    self.online_devices.push(device);
                 }
-                cmodel::AnyAction::BoundedOutputSet {
-                    behavior,
-                    target,
-                    min,
-                    max,
-                } => {
-                    // TODO Can this calculation be improved?
-                    let min = min.value() as f64;
-                    let max = max.value() as f64;
-
-                    let sensor_value = online_rule.sensor.read_cached().celsius() as f64;
-                    let maxval = behavior.cond_max_value as f64;
-                    let minval = behavior.cond_min_value as f64;
-                    let progress = (sensor_value - minval) / (maxval - minval);
-
-                    let output_per =
-                        Percent::try_from((min + (progress * (max - min))) as i32).unwrap();
-
-                    // TODO Same key shouldn't exist already on the
-                    // map. This must be checked on the semantic
-                    // analysis of the config.
-                    computed
-                        .output_values
-                        .insert(target.as_ref().into(), output_per);
+                None => {
+                    info!(
+                        "Device `{}` is no longer defined or has changed. Releasing it.",
+                        device.name()
+                    );
+                    device.release_outputs();
                 }
-                cmodel::AnyAction::FixedOutputSet { target, value } => {
-                    computed.output_values.insert(target.as_ref().into(), value);
+            }
+        }
+
+        for (symbol, location) in found_devices.into_iter() {
+            info!("Found device `{}` at {:?}", symbol.name, location);
+            let device = create_online_device_for_symbol(self, symbol, location);
+            self.online_devices.push(device);
+        }
+
+        Ok(())
+    }
+
+    /// Hands back the control of all the outputs the program has
+    /// touched on the online devices.
+    pub fn release_outputs(&self) {
+        for device in self.online_devices.iter() {
+            device.release_outputs();
+        }
+    }
+
+    fn apply_failure_action(&self, output: &SymbolOutput, action: &ast::FailureAction) {
+        guard!(let Some(device) = self.find_device(&output.device.name) else {
+            return;
+        });
+
+        let result = match action {
+            ast::FailureAction::Keep => Ok(()),
+            ast::FailureAction::Full => device.write_output(output.index, PwmMode::Full),
+            ast::FailureAction::Auto => device
+                .write_output(output.index, PwmMode::Auto)
+                .or_else(|err| {
+                    warn!(
+                        "Cannot set output `{}` to automatic mode: {}. Setting it to full speed.",
+                        output.name, err
+                    );
+                    device.write_output(output.index, PwmMode::Full)
+                }),
+        };
+
+        if let Err(err) = result {
+            error!(
+                "Cannot apply failure policy to output `{}`: {}",
+                output.name, err
+            );
+        }
+    }
+
+    /// Writes a value to the given output, keeping track of it.
+    pub fn write_output_value(
+        &self,
+        device: &OnlineDevice,
+        output: &SymbolOutput,
+        value: Percent,
+    ) -> std::io::Result<()> {
+        device
+            .write_output(output.index, PwmMode::ManualPercent(value))
+            .map_err(|err| {
+                self.record_io_error(&output.device.name);
+                err
+            })?;
+        self.written_outputs
+            .borrow_mut()
+            .insert(output.name.clone(), value);
+        Ok(())
+    }
+
+    /// Computes the value to write to the output from the value set
+    /// by the rules, applying its limits, ramps and spin up.
+    pub fn output_value(
+        &self,
+        output: &SymbolOutput,
+        target: Percent,
+        interval: Duration,
+    ) -> Percent {
+        let last = self.written_outputs.borrow().get(&output.name).copied();
+        self.output_shaper
+            .borrow_mut()
+            .shape(output, target, last, interval, Instant::now())
+    }
+
+    /// Returns whether the outputs of the given device are being held
+    /// at full speed because of a stall alarm.
+    pub fn device_forced_full(&self, device_name: &str) -> bool {
+        self.thermal_program
+            .stall_alarm
+            .actions
+            .iter()
+            .any(|action| matches!(action, ast::AlarmAction::SetDeviceFull))
+            && self
+                .stalled_outputs
+                .borrow()
+                .values()
+                .any(|stalled| stalled.alarm_raised && stalled.device == device_name)
+    }
+
+    fn force_device_full(&self, device: &SymbolDevice) {
+        guard!(let Some(online_device) = self.find_device(&device.name) else {
+            return;
+        });
+
+        for output in self
+            .thermal_program
+            .symbol_table
+            .get_all_symbols_of_type::<SymbolOutput>()
+            .into_iter()
+            .filter(|output| output.device.name == device.name)
+        {
+            match online_device.write_output(output.index, PwmMode::Full) {
+                Ok(()) => {
+                    self.written_outputs
+                        .borrow_mut()
+                        .insert(output.name.clone(), Percent::try_from(100).unwrap());
                 }
+                Err(err) => error!(
+                    "Cannot set output `{}` to full speed: {}",
+                    output.name, err
+                ),
             }
         }
+    }
 
-        computed
+    fn run_alarm_command(&self, command: &str, output: &SymbolOutput) {
+        if self.dryrun {
+            info!("Dry run: not running alarm command `{}`.", command);
+            return;
+        }
+
+        match Command::new("/bin/sh")
+            .arg("-c")
+            .arg(command)
+            .env("FANCONTROL_OUTPUT", &output.name)
+            .env("FANCONTROL_DEVICE", &output.device.name)
+            .spawn()
+        {
+            Ok(mut child) => {
+                // Reap the child without blocking the control loop.
+                std::thread::spawn(move || child.wait());
+            }
+            Err(err) => error!("Cannot run alarm command `{}`: {}", command, err),
+        }
     }
-}
 
-fn print_log(rule: &When, sensor: &OnlineSensor) {
-    rule_info!(@ rule.rule_name();
-        "Value of {} is {}.",
-        sensor.symbol.name,
-        sensor.read_cached()
-    );
-}
+    fn raise_stall_alarm(&self, output: &SymbolOutput, tachometer: &SymbolSensor, value: Percent) {
+        let alarm = &self.thermal_program.stall_alarm;
+        error!(
+            "Output `{}` has stalled: it is set to {} but tachometer `{}` has read 0 RPM for more than {:?}.",
+            output.name, value, tachometer.name, alarm.grace_period
+        );
 
-enum ValueDiff<V1, V2> {
-    Left(V1),
-    Right(V2),
-    Both(V1, V2),
-}
+        for action in alarm.actions.iter() {
+            match action {
+                ast::AlarmAction::Exec(command) => self.run_alarm_command(command, output),
+                ast::AlarmAction::SetDeviceFull => self.force_device_full(&output.device),
+            }
+        }
+    }
 
-fn diff_maps_into<K: Hash + Eq, V1, V2>(
-    left: HashMap<K, V1>,
-    mut right: HashMap<K, V2>,
-) -> HashMap<K, ValueDiff<V1, V2>> {
-    let mut result = HashMap::new();
+    /// Checks whether any of the outputs that have a tachometer has
+    /// stalled, raising the stall alarm once an output has been
+    /// stalled for longer than the grace period.
+    pub fn check_stalled_outputs(&self, now: Instant) {
+        let alarm = &self.thermal_program.stall_alarm;
 
-    for (lkey, lvalue) in left.into_iter() {
-        match right.remove_entry(&lkey) {
-            Some((_, rvalue)) => {
-                result.insert(lkey, ValueDiff::Both(lvalue, rvalue));
+        for output in self
+            .thermal_program
+            .symbol_table
+            .get_all_symbols_of_type::<SymbolOutput>()
+        {
+            guard!(let Some(tachometer) = output.tachometer.as_ref() else {
+                continue;
+            });
+            guard!(let Some(device) = self.find_device(&tachometer.device.name) else {
+                continue;
+            });
+            let value = match self.written_outputs.borrow().get(&output.name) {
+                Some(&value) => value,
+                None => continue,
+            };
+
+            let rpm = match device
+                .sensor_index(tachometer)
+                .and_then(|index| device.read_fan(index))
+            {
+                Ok(rpm) => rpm,
+                Err(err) => {
+                    self.record_io_error(&tachometer.device.name);
+                    warn!(
+                        "Cannot read tachometer `{}` of output `{}`: {}",
+                        tachometer.name, output.name, err
+                    );
+                    continue;
+                }
+            };
+
+            let mut stalled_outputs = self.stalled_outputs.borrow_mut();
+            if value <= alarm.threshold || rpm.rpm() > 0 {
+                if let Some(stalled) = stalled_outputs.remove(&output.name) {
+                    if stalled.alarm_raised {
+                        info!("Output `{}` is spinning again ({}).", output.name, rpm);
+                    }
+                }
+                continue;
             }
-            None => {
-                result.insert(lkey, ValueDiff::Left(lvalue));
+
+            let stalled = stalled_outputs
+                .entry(output.name.clone())
+                .or_insert_with(|| StalledOutput::new(output.device.name.clone(), now));
+            if !stalled.alarm_raised && now.duration_since(stalled.since) >= alarm.grace_period {
+                stalled.alarm_raised = true;
+                drop(stalled_outputs);
+                self.raise_stall_alarm(output, tachometer, value);
             }
         }
     }
 
-    for (rkey, rvalue) in right.into_iter() {
-        result.insert(rkey, ValueDiff::Right(rvalue));
+    pub fn record_io_error(&self, device_name: &str) {
+        *self
+            .io_errors
+            .borrow_mut()
+            .entry(device_name.to_string())
+            .or_insert(0) += 1;
     }
 
-    result
-}
+    pub fn record_hotplug_event(&self, device_name: &str, action: &'static str) {
+        *self
+            .hotplug_events
+            .borrow_mut()
+            .entry((device_name.to_string(), action))
+            .or_insert(0) += 1;
+    }
 
-fn priorization_fun<'prog>(
-    pri: ast::OutputPriorization,
-) -> fn(
-    CombinedRuleOutputValue<'prog>,
-    CombinedRuleOutputValue<'prog>,
-) -> CombinedRuleOutputValue<'prog> {
-    match pri {
-        ast::OutputPriorization::Latest => |_, r| r,
-        ast::OutputPriorization::Min => |l, r| {
-            if l.value > r.value {
-                r
-            } else {
-                l
+    /// Renders the current state of the program in the Prometheus
+    /// text exposition format.
+    pub fn render_metrics(&self) -> String {
+        let symbol_table = &self.thermal_program.symbol_table;
+        let mut writer = metrics::MetricsWriter::new();
+
+        // Sensors are not read again on each scrape, so scrapes don't
+        // add I/O or affect the I/O error counters.
+        let last_readings = self.last_readings.borrow();
+        let mut temperatures = Vec::new();
+        let mut fan_speeds = Vec::new();
+        for sensor in symbol_table.get_all_symbols_of_type::<SymbolSensor>() {
+            match last_readings.get(&sensor.name) {
+                Some(SensorValue::Temp(temp)) => temperatures.push((sensor, *temp)),
+                Some(SensorValue::Fan(rpm)) => fan_speeds.push((sensor, *rpm)),
+                None => {}
             }
-        },
-        ast::OutputPriorization::Max => |l, r| {
-            if l.value > r.value {
-                l
+        }
+
+        writer.family(
+            "fancontrol_sensor_temperature_celsius",
+            "Temperature read from the sensor on the last evaluation of the rules.",
+            metrics::MetricKind::Gauge,
+        );
+        for (sensor, temp) in temperatures {
+            writer.sample(
+                "fancontrol_sensor_temperature_celsius",
+                &[
+                    ("sensor", sensor.name.as_str()),
+                    ("device", sensor.device.name.as_str()),
+                ],
+                temp.mcelsius() as f64 / 1000.0,
+            );
+        }
+
+        writer.family(
+            "fancontrol_sensor_fan_rpm",
+            "Fan speed read from the sensor on the last evaluation of the rules.",
+            metrics::MetricKind::Gauge,
+        );
+        for (sensor, rpm) in fan_speeds {
+            writer.sample(
+                "fancontrol_sensor_fan_rpm",
+                &[
+                    ("sensor", sensor.name.as_str()),
+                    ("device", sensor.device.name.as_str()),
+                ],
+                rpm.rpm() as f64,
+            );
+        }
+
+        let written_outputs = self.written_outputs.borrow();
+        let outputs: Vec<(&Rc<SymbolOutput>, Percent)> = symbol_table
+            .get_all_symbols_of_type::<SymbolOutput>()
+            .into_iter()
+            .filter_map(|output| {
+                written_outputs
+                    .get(&output.name)
+                    .map(|&value| (output, value))
+            })
+            .collect();
+
+        writer.family(
+            "fancontrol_output_percent",
+            "Last value written to the output, in percent.",
+            metrics::MetricKind::Gauge,
+        );
+        for (output, value) in outputs.iter() {
+            writer.sample(
+                "fancontrol_output_percent",
+                &[
+                    ("output", output.name.as_str()),
+                    ("device", output.device.name.as_str()),
+                ],
+                value.value() as f64,
+            );
+        }
+
+        writer.family(
+            "fancontrol_output_pwm",
+            "Last raw PWM value (0-255) written to the output.",
+            metrics::MetricKind::Gauge,
+        );
+        for (output, value) in outputs.iter() {
+            writer.sample(
+                "fancontrol_output_pwm",
+                &[
+                    ("output", output.name.as_str()),
+                    ("device", output.device.name.as_str()),
+                ],
+                value.point_at_range(0u8, 255u8) as f64,
+            );
+        }
+
+        writer.family(
+            "fancontrol_rule_triggered",
+            "Whether the rule was triggered on the last evaluation.",
+            metrics::MetricKind::Gauge,
+        );
+        let rule_engine = self.rule_engine.borrow();
+        for rule in self.thermal_program.rules.iter() {
+            let triggered = rule_engine.is_rule_triggered(rule.rule_index);
+            writer.sample(
+                "fancontrol_rule_triggered",
+                &[("rule", rule.rule_name().as_ref())],
+                if triggered { 1.0 } else { 0.0 },
+            );
+        }
+
+        writer.family(
+            "fancontrol_device_online",
+            "Whether the device is currently online.",
+            metrics::MetricKind::Gauge,
+        );
+        for device in symbol_table.get_all_symbols_of_type::<SymbolDevice>() {
+            let online = self.find_device(&device.name).is_some();
+            writer.sample(
+                "fancontrol_device_online",
+                &[("device", device.name.as_str())],
+                if online { 1.0 } else { 0.0 },
+            );
+        }
+
+        writer.family(
+            "fancontrol_io_errors_total",
+            "Number of failed reads or writes on the device.",
+            metrics::MetricKind::Counter,
+        );
+        for (device, count) in self.io_errors.borrow().iter() {
+            writer.sample(
+                "fancontrol_io_errors_total",
+                &[("device", device.as_str())],
+                *count as f64,
+            );
+        }
+
+        writer.family(
+            "fancontrol_hotplug_events_total",
+            "Number of times the device has been plugged in or unplugged.",
+            metrics::MetricKind::Counter,
+        );
+        for ((device, action), count) in self.hotplug_events.borrow().iter() {
+            writer.sample(
+                "fancontrol_hotplug_events_total",
+                &[("device", device.as_str()), ("action", *action)],
+                *count as f64,
+            );
+        }
+
+        writer.finish()
+    }
+
+    /// Keeps the values successfully read on the last evaluation of
+    /// the rules, for exporting them through the metrics.
+    pub fn update_last_readings(&self, readings: &HashMap<&str, SensorResult>) {
+        *self.last_readings.borrow_mut() = readings
+            .iter()
+            .filter_map(|(&name, reading)| {
+                reading
+                    .as_ref()
+                    .ok()
+                    .map(|&value| (name.to_string(), value))
+            })
+            .collect();
+    }
+
+    /// Collects the current state of the sensors and outputs for
+    /// recording it. Sensors already read on the evaluation of the
+    /// rules are not read again.
+    pub fn record_row(
+        &self,
+        readings: &HashMap<&str, SensorResult>,
+        output_statuses: &[control::OutputStatus],
+    ) -> record::RecordRow {
+        let symbol_table = &self.thermal_program.symbol_table;
+
+        let sensors = symbol_table
+            .get_all_symbols_of_type::<SymbolSensor>()
+            .into_iter()
+            .map(|sensor| {
+                let value = match readings.get(sensor.name.as_str()) {
+                    Some(reading) => reading.as_ref().ok().copied(),
+                    None if self.find_device(&sensor.device.name).is_some() => {
+                        self.read_sensor(sensor).ok()
+                    }
+                    None => None,
+                };
+                (sensor.name.clone(), value.map(SensorValue::value))
+            })
+            .collect();
+
+        let written_outputs = self.written_outputs.borrow();
+        let outputs = symbol_table
+            .get_all_symbols_of_type::<SymbolOutput>()
+            .into_iter()
+            .map(|output| {
+                let rule = if self.is_overridden(&output.name) {
+                    Some("override".to_string())
+                } else if self.device_forced_full(&output.device.name) {
+                    Some("stall".to_string())
+                } else {
+                    output_statuses
+                        .iter()
+                        .find(|status| status.output == output.name)
+                        .and_then(|status| status.rule.clone())
+                };
+
+                let value = written_outputs.get(&output.name).map(|value| value.value());
+                (output.name.clone(), record::RecordedOutput::new(value, rule))
+            })
+            .collect();
+
+        record::RecordRow::new(record::format_timestamp(SystemTime::now()), sensors, outputs)
+    }
+
+    /// Returns whether the given output is currently overridden
+    /// through the control socket.
+    pub fn is_overridden(&self, output_name: &str) -> bool {
+        self.overrides.borrow().contains_key(output_name)
+    }
+
+    /// Drops the expired overrides, and writes the value of the
+    /// remaining ones to their outputs.
+    pub fn apply_overrides(&self, now: Instant) {
+        let symbol_table = &self.thermal_program.symbol_table;
+        let mut overrides = self.overrides.borrow_mut();
+        overrides.retain(|name, output_override| {
+            if symbol_table.require_type::<SymbolOutput>(name).is_err() {
+                info!("Output `{}` is no longer defined. Dropping its override.", name);
+                false
+            } else if now >= output_override.until {
+                info!("Override of output `{}` has expired.", name);
+                false
             } else {
-                r
+                true
+            }
+        });
+
+        for (name, output_override) in overrides.iter() {
+            guard!(let Ok(output) = symbol_table.require_type::<SymbolOutput>(name) else {
+                continue;
+            });
+
+            if self.device_forced_full(&output.device.name) {
+                continue;
+            }
+
+            if let Some(device) = self.find_device(&output.device.name) {
+                if let Err(err) = self.write_output_value(device, output, output_override.value) {
+                    warn!("Cannot write override of output `{}`: {}", name, err);
+                }
+            }
+        }
+    }
+
+    fn read_sensors(&self) -> Vec<control::SensorReading> {
+        self.thermal_program
+            .symbol_table
+            .get_all_symbols_of_type::<SymbolSensor>()
+            .into_iter()
+            .map(|sensor| {
+                let name = sensor.name.clone();
+                let device_name = sensor.device.name.clone();
+                match self.read_sensor(sensor) {
+                    Ok(SensorValue::Temp(temp)) => control::SensorReading::new(
+                        name,
+                        device_name,
+                        Some(temp.mcelsius() as f64 / 1000.0),
+                        Some("C"),
+                        None,
+                    ),
+                    Ok(SensorValue::Fan(rpm)) => control::SensorReading::new(
+                        name,
+                        device_name,
+                        Some(rpm.rpm() as f64),
+                        Some("RPM"),
+                        None,
+                    ),
+                    Err(err) => control::SensorReading::new(
+                        name,
+                        device_name,
+                        None,
+                        None,
+                        Some(err.to_string()),
+                    ),
+                }
+            })
+            .collect()
+    }
+
+    fn rules_status(&self) -> control::RulesStatus {
+        let last_evaluation = self.last_evaluation.borrow();
+        let overrides = self.overrides.borrow();
+
+        let mut outputs: Vec<control::OutputStatus> = last_evaluation
+            .outputs
+            .iter()
+            .filter(|status| !overrides.contains_key(&status.output))
+            .cloned()
+            .collect();
+        outputs.extend(overrides.iter().map(|(name, output_override)| {
+            control::OutputStatus::new(name.clone(), output_override.value.value(), None, true)
+        }));
+
+        control::RulesStatus {
+            triggered: last_evaluation.triggered.clone(),
+            outputs,
+        }
+    }
+
+    pub fn handle_control_request(&self, request: control::Request) -> control::Response {
+        match request {
+            control::Request::GetSensors => control::Response::ok(self.read_sensors()),
+            control::Request::GetRules => control::Response::ok(self.rules_status()),
+            control::Request::Override {
+                output,
+                value,
+                seconds,
+            } => {
+                if self
+                    .thermal_program
+                    .symbol_table
+                    .require_type::<SymbolOutput>(&output)
+                    .is_err()
+                {
+                    return control::Response::error(format!("Unknown output `{}`", output));
+                }
+
+                guard!(let Ok(value) = Percent::try_from(value) else {
+                    return control::Response::error(format!("Invalid percentage: {}", value));
+                });
+
+                if seconds == 0 {
+                    return control::Response::error("The override must last at least 1 second");
+                }
+
+                info!(
+                    "Overriding output `{}` to {} for {} seconds.",
+                    output, value, seconds
+                );
+                self.overrides.borrow_mut().insert(
+                    output,
+                    OutputOverride::new(value, Instant::now() + Duration::from_secs(seconds)),
+                );
+                control::Response::ok(())
+            }
+            control::Request::ClearOverrides { output } => {
+                let mut overrides = self.overrides.borrow_mut();
+                let cleared: Vec<String> = match output {
+                    Some(name) => match overrides.remove_entry(&name) {
+                        Some((name, _)) => vec![name],
+                        None => {
+                            return control::Response::error(format!(
+                                "Output `{}` is not overridden",
+                                name
+                            ))
+                        }
+                    },
+                    None => overrides.drain().map(|(name, _)| name).collect(),
+                };
+
+                for name in cleared.iter() {
+                    info!("Cleared override of output `{}`.", name);
+                }
+                control::Response::ok(cleared)
+            }
+        }
+    }
+
+    /// Keeps track of the rules that couldn't be evaluated on the last
+    /// evaluation, warning about them on the first failure and then
+    /// once every `RULE_FAILURE_WARN_EVERY` consecutive failures, so
+    /// rules with no outputs don't fail silently.
+    pub fn update_rule_failures(&self, failed_rules: &[(&When, String)]) {
+        let mut rule_failures = self.rule_failures.borrow_mut();
+
+        rule_failures.retain(|name, count| {
+            let recovered = !failed_rules
+                .iter()
+                .any(|(rule, _)| rule.rule_name() == name.as_str());
+            if recovered {
+                info!(
+                    "Rule `{}` recovered after {} failed evaluations.",
+                    name, count
+                );
+            }
+            !recovered
+        });
+
+        for (rule, reason) in failed_rules.iter() {
+            let count = rule_failures
+                .entry(rule.rule_name().into_owned())
+                .or_insert(0);
+            *count += 1;
+
+            if *count == 1 {
+                rule_warn!(@ rule.rule_name(); "Cannot evaluate rule: {}", reason);
+            } else if *count % RULE_FAILURE_WARN_EVERY == 0 {
+                rule_warn!(@ rule.rule_name();
+                    "Cannot evaluate rule for {} evaluations in a row: {}",
+                    count,
+                    reason
+                );
+            }
+        }
+    }
+
+    /// Keeps track of the outputs that have failed on the last
+    /// evaluation, along with the reason of the failure. Outputs that
+    /// exceed the retries of their failure policy enter failsafe mode,
+    /// and outputs that didn't fail this time are considered recovered.
+    pub fn update_output_failures(&self, failed_outputs: HashMap<ComputedRuleOutputKey, String>) {
+        let mut output_failures = self.output_failures.borrow_mut();
+
+        output_failures.retain(|name, failure| {
+            let recovered = !failed_outputs.keys().any(|key| &key.output.name == name);
+            if recovered && failure.failsafe {
+                info!(
+                    "Output `{}` recovered after {} failed evaluations. Leaving failsafe mode.",
+                    name, failure.count
+                );
+            } else if recovered {
+                info!(
+                    "Output `{}` recovered after {} failed evaluations.",
+                    name, failure.count
+                );
+            }
+            !recovered
+        });
+
+        for (key, reason) in failed_outputs.into_iter() {
+            let output = key.output;
+            let policy = self.thermal_program.failure_policy_for(&output.device);
+            let failure = output_failures
+                .entry(output.name.clone())
+                .or_insert_with(OutputFailure::default);
+            failure.count += 1;
+
+            if failure.count <= policy.retries {
+                warn!(
+                    "Output `{}` failed ({} of {} retries): {}",
+                    output.name, failure.count, policy.retries, reason
+                );
+            } else if !failure.failsafe {
+                failure.failsafe = true;
+                error!(
+                    "Output `{}` failed: {}. Entering failsafe mode (ON FAILURE SET {}).",
+                    output.name, reason, policy.action
+                );
+                self.apply_failure_action(output, &policy.action);
             }
+        }
+    }
+}
+
+fn print_log(rule: &When, value: SensorValue) {
+    rule_info!(@ rule.rule_name();
+        "Value of {} is {}.",
+        rule.sensor.name(),
+        value
+    );
+}
+
+fn run_ctl(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let request = match matches.subcommand() {
+        ("sensors", _) => control::Request::GetSensors,
+        ("rules", _) => control::Request::GetRules,
+        ("override", Some(args)) => control::Request::Override {
+            output: args.value_of("output").unwrap().to_string(),
+            value: clap::value_t_or_exit!(args.value_of("percent"), i32),
+            seconds: clap::value_t_or_exit!(args.value_of("seconds"), u64),
         },
+        ("clear", Some(args)) => control::Request::ClearOverrides {
+            output: args.value_of("output").map(|output| output.to_string()),
+        },
+        _ => unreachable!(),
+    };
+
+    let socket = matches.value_of("socket").unwrap();
+    let response = control::send_request(socket, &request)
+        .map_err(|err| format!("Cannot send command through {}: {}", socket, err))?;
+
+    match response {
+        control::Response::Ok { data } => {
+            println!("{}", serde_json::to_string_pretty(&data)?);
+            Ok(())
+        }
+        control::Response::Error { message } => Err(message.into()),
     }
 }
 
+fn load_program(config_path: &str) -> Result<cmodel::ThermalProgram, Box<dyn Error>> {
+    let source = std::fs::read_to_string(config_path)
+        .map_err(|err| format!("Cannot read {}: {}", config_path, err))?;
+
+    let (program, warnings) = config::compile(config_path, &source)?;
+    for warning in warnings {
+        warn!("{}", warning);
+    }
+    Ok(program)
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
         .format(|buf, record| {
@@ -521,6 +1484,7 @@ fn main() -> Result<(), Box<dyn Error>> {
         .version("1.0")
         .author("devcexx")
         .about("System monitor & fan control")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("config")
                 .short("c")
@@ -550,8 +1514,167 @@ fn main() -> Result<(), Box<dyn Error>> {
 		.help("Defines how much time should the program wait at most when starting to allow all devices to become full available, in seconds. A value of 0 will indicate that no wait will be performed.")
 		.default_value("30")
 	)
+        .arg(
+	    Arg::with_name("control-socket")
+		.short("s")
+		.long("control-socket")
+		.value_name("PATH")
+		.help("Listens for commands on a Unix domain socket at the given path. See the ctl subcommand.")
+		.takes_value(true)
+	)
+        .arg(
+	    Arg::with_name("device-dir")
+		.long("device-dir")
+		.value_name("DIR")
+		.help("Looks for the devices in the given directory instead of through udev. Each device is the subdirectory named after its UDEV TAG, with the same layout as the sysfs directory of a hwmon device. Devices are considered plugged in or unplugged when their directory is created or removed. Useful along with the \"mock\" driver for testing configurations without the actual hardware.")
+		.takes_value(true)
+	)
+        .arg(
+	    Arg::with_name("record")
+		.long("record")
+		.value_name("FILE")
+		.help("Appends the values of the sensors and outputs to the given file on each evaluation of the rules.")
+		.takes_value(true)
+	)
+        .arg(
+	    Arg::with_name("record-format")
+		.long("record-format")
+		.value_name("FORMAT")
+		.help("Format of the record file: csv or jsonl. By default, it is guessed from the extension of the file, defaulting to csv.")
+		.possible_values(&["csv", "jsonl"])
+		.takes_value(true)
+	)
+        .arg(
+	    Arg::with_name("record-max-size")
+		.long("record-max-size")
+		.value_name("SIZE")
+		.help("Size at which the record file is rotated, in bytes. Accepts K, M and G suffixes.")
+		.default_value("10M")
+	)
+        .arg(
+	    Arg::with_name("record-keep")
+		.long("record-keep")
+		.value_name("COUNT")
+		.help("Number of rotated record files to keep, named as the record file followed by .1, .2, and so on.")
+		.default_value("5")
+	)
+        .arg(
+	    Arg::with_name("metrics-listen")
+		.long("metrics-listen")
+		.value_name("ADDRESS")
+		.help("Serves metrics in the Prometheus text format over HTTP on the given address (e.g. 127.0.0.1:9797), at the /metrics path.")
+		.takes_value(true)
+	)
+        .subcommand(
+	    SubCommand::with_name("check")
+		.about("Checks a configuration file without running it. Exits with status 0 if it is valid, 1 if it has errors, or 2 if some device or attribute it refers to cannot be found when verifying it")
+		.arg(
+		    Arg::with_name("config")
+			.short("c")
+			.long("config")
+			.value_name("FILE")
+			.help("Specifies the path of the configuration file")
+			.required(true)
+		)
+		.arg(
+		    Arg::with_name("verify")
+			.long("verify")
+			.help("Also looks up each device by its UDEV TAG, and checks that the attributes backing its sensors and outputs exist, reporting the outcome for each of them")
+		)
+		.arg(
+		    Arg::with_name("device-dir")
+			.long("device-dir")
+			.value_name("DIR")
+			.help("Looks up the devices in the given directory instead of through udev when verifying the configuration")
+			.requires("verify")
+			.takes_value(true)
+		)
+	)
+        .subcommand(
+	    SubCommand::with_name("simulate")
+		.about("Runs the rules of a configuration against the sensor values recorded in a CSV trace, and writes as CSV the value each output would have taken")
+		.arg(
+		    Arg::with_name("config")
+			.short("c")
+			.long("config")
+			.value_name("FILE")
+			.help("Specifies the path of the configuration file")
+			.required(true)
+		)
+		.arg(
+		    Arg::with_name("trace")
+			.short("t")
+			.long("trace")
+			.value_name("FILE")
+			.help("CSV file with a timestamp on its first column, followed by a column for each sensor, named after it on the header. Timestamps are either a number of seconds or a YYYY-MM-DD hh:mm:ss date, as in the record files. Temperatures are expressed in degrees Celsius, and fan speeds in RPM. Empty values simulate failed reads.")
+			.required(true)
+		)
+		.arg(
+		    Arg::with_name("output")
+			.short("o")
+			.long("output")
+			.value_name("FILE")
+			.help("Writes the result to the given file instead of to the standard output")
+		)
+	)
+        .subcommand(
+	    SubCommand::with_name("ctl")
+		.about("Sends a command to a running instance of the program through its control socket")
+		.setting(AppSettings::SubcommandRequiredElseHelp)
+		.arg(
+		    Arg::with_name("socket")
+			.short("s")
+			.long("socket")
+			.value_name("PATH")
+			.help("Path of the control socket of the running instance")
+			.default_value(control::DEFAULT_SOCKET_PATH)
+		)
+		.subcommand(SubCommand::with_name("sensors").about("Shows the current value of the sensors"))
+		.subcommand(SubCommand::with_name("rules").about("Shows the triggered rules, and which rule set the value of each output"))
+		.subcommand(
+		    SubCommand::with_name("override")
+			.about("Sets an output to a fixed value for some time, ignoring the rules")
+			.arg(Arg::with_name("output").required(true).index(1))
+			.arg(Arg::with_name("percent").required(true).index(2))
+			.arg(Arg::with_name("seconds").required(true).index(3))
+		)
+		.subcommand(
+		    SubCommand::with_name("clear")
+			.about("Clears the override of an output, or all of them if no output is given")
+			.arg(Arg::with_name("output").index(1))
+		)
+	)
         .get_matches();
 
+    if let Some(ctl_matches) = matches.subcommand_matches("ctl") {
+        return run_ctl(ctl_matches);
+    }
+
+    if let Some(check_matches) = matches.subcommand_matches("check") {
+        let discovery = if check_matches.is_present("verify") {
+            Some(match check_matches.value_of("device-dir") {
+                Some(dir) => DeviceDiscovery::Directory(dir.into()),
+                None => DeviceDiscovery::Udev,
+            })
+        } else {
+            None
+        };
+
+        std::process::exit(check::run(
+            check_matches.value_of("config").unwrap(),
+            discovery.as_ref(),
+        ));
+    }
+
+    if let Some(simulate_matches) = matches.subcommand_matches("simulate") {
+        let program = load_program(simulate_matches.value_of("config").unwrap())?;
+        return simulate::run_files(
+            &program,
+            simulate_matches.value_of("trace").unwrap(),
+            simulate_matches.value_of("output"),
+        );
+    }
+
     let config_path = matches.value_of("config").unwrap();
     let dryrun = matches.is_present("dry-run");
     let interval = Duration::from_millis(clap::value_t_or_exit!(matches.value_of("interval"), u64));
@@ -561,24 +1684,72 @@ fn main() -> Result<(), Box<dyn Error>> {
     ));
 
     info!("Initializing fan control...");
-    let conf_program = config::conffile::ProgramParser::new()
-        .parse(&std::fs::read_to_string(config_path)?)
-        .map_err::<Box<dyn Error>, _>(|err| err.to_string().into())?;
+    signals::install_shutdown_handlers()?;
+    signals::install_reload_handler()?;
+    let program = load_program(config_path)?;
+    let mut control_server = match matches.value_of("control-socket") {
+        Some(path) => {
+            let server = control::ControlServer::bind(path)
+                .map_err(|err| format!("Cannot listen on control socket {}: {}", path, err))?;
+            info!("Listening for commands on {}", server.path().display());
+            Some(server)
+        }
+        None => None,
+    };
+    let mut metrics_server = match matches.value_of("metrics-listen") {
+        Some(_) => {
+            let addr = clap::value_t_or_exit!(matches.value_of("metrics-listen"), SocketAddr);
+            let server = metrics::MetricsServer::bind(addr)
+                .map_err(|err| format!("Cannot listen for metrics on {}: {}", addr, err))?;
+            info!("Serving metrics on http://{}/metrics", server.local_addr()?);
+            Some(server)
+        }
+        None => None,
+    };
 
-    let program = match config::check_program(conf_program) {
-        Ok(p) => p,
-        Err(e) => panic!("Configuration error: {}", e),
+    let mut recorder = match matches.value_of("record") {
+        Some(path) => {
+            let path = PathBuf::from(path);
+            let format = match matches.value_of("record-format") {
+                Some(name) => record::RecordFormat::from_name(name)
+                    .ok_or_else(|| format!("Unknown record format: {}", name))?,
+                None => record::RecordFormat::from_path(&path),
+            };
+            let max_size = matches.value_of("record-max-size").unwrap();
+            let max_size = record::parse_size(max_size)
+                .ok_or_else(|| format!("Invalid record size: {}", max_size))?;
+            let keep = clap::value_t_or_exit!(matches.value_of("record-keep"), u32);
+
+            info!("Recording history to {} ({:?})", path.display(), format);
+            Some(record::Recorder::new(path, format, max_size, keep))
+        }
+        None => None,
     };
 
-    let mut udev_poller = UdevPoller::poll_on(
-        MonitorBuilder::new()?
-            .match_tag(UDEV_FANCONTROL_TAG)?
-            .listen()?,
-    );
+    let discovery = match matches.value_of("device-dir") {
+        Some(dir) => DeviceDiscovery::Directory(dir.into()),
+        None => DeviceDiscovery::Udev,
+    };
+
+    let mut udev_poller = match discovery {
+        DeviceDiscovery::Udev => Some(UdevPoller::poll_on(
+            MonitorBuilder::new()?
+                .match_tag(UDEV_FANCONTROL_TAG)?
+                .listen()?,
+        )),
+        DeviceDiscovery::Directory(_) => None,
+    };
 
     info!("Discovering devices...");
 
-    let mut context: RunContext = RunContext::new(&program, Vec::new(), Vec::new(), dryrun);
+    let program = Rc::new(program);
+    let mut context: RunContext = RunContext::new(
+        program.clone(),
+        Vec::new(),
+        Vec::new(),
+        dryrun,
+        discovery,
+    );
 
     // TODO Multiple devices must not be identified by the same udev tag.
     for device_symbol in program
@@ -586,23 +1757,17 @@ fn main() -> Result<(), Box<dyn Error>> {
         .get_all_symbols_of_type::<SymbolDevice>()
         .into_iter()
     {
-        match udev_find_with_tags(vec![&device_symbol.tag]) {
-            Some(udev_dev) => {
-                info!(
-                    "Found device `{}` at {:?}",
-                    device_symbol.name,
-                    &udev_dev.devpath()
-                );
+        match context.discovery.find(&device_symbol.tag) {
+            Some(location) => {
+                info!("Found device `{}` at {:?}", device_symbol.name, location);
 
-                context.online_devices.push(create_online_device_for_symbol(
-                    &context,
-                    device_symbol,
-                    udev_dev,
-                ));
+                let device =
+                    create_online_device_for_symbol(&context, device_symbol.clone(), location);
+                context.online_devices.push(device);
             }
 
             None => {
-                context.offline_devices.push(device_symbol);
+                context.offline_devices.push(device_symbol.clone());
             }
         }
     }
@@ -627,16 +1792,22 @@ fn main() -> Result<(), Box<dyn Error>> {
             elapsed
         } < discover_timeout
             && context.any_non_hotpluggable_device_offline()
+            && !signals::shutdown_requested()
         {
             let remaining = discover_timeout - elapsed;
-            poll_device_events(
+            poll_devices(
                 &mut context,
-                &mut udev_poller,
+                udev_poller.as_mut(),
                 PollMode::WaitTimeout(remaining),
             )
         }
     }
 
+    if signals::shutdown_requested() {
+        info!("Shutting down...");
+        return Ok(());
+    }
+
     let remaining_mandatory_devs = context
         .filter_non_hotpluggable_offline_devices()
         .map(|dev| dev.name.as_ref())
@@ -651,8 +1822,27 @@ fn main() -> Result<(), Box<dyn Error>> {
     }
 
     loop {
+        if signals::shutdown_requested() {
+            info!("Shutting down...");
+            context.release_outputs();
+            return Ok(());
+        }
+
+        if signals::reload_requested() {
+            info!("Reloading configuration from {}...", config_path);
+            match load_program(config_path)
+                .and_then(|program| context.replace_program(Rc::new(program)))
+            {
+                Ok(()) => info!("Configuration reloaded."),
+                Err(err) => error!(
+                    "Cannot reload configuration, keeping the current one:\n{}",
+                    err
+                ),
+            }
+        }
+
         let start_time = Instant::now();
-        poll_device_events(&mut context, &mut udev_poller, PollMode::NoWait);
+        poll_devices(&mut context, udev_poller.as_mut(), PollMode::NoWait);
         let offline_non_hotpluggable_devices = context
             .filter_non_hotpluggable_offline_devices()
             .map(|dev| dev.name.as_ref())
@@ -662,70 +1852,74 @@ fn main() -> Result<(), Box<dyn Error>> {
                 "One or more non hot-plugabble device has been removed: {}",
                 offline_non_hotpluggable_devices.join(", ")
             );
+            context.release_outputs();
             std::process::exit(EXIT_CODE_HOT_UNPLUG);
         }
 
-        let online_rules: Vec<OnlineThermalRule> = context.get_online_rules();
-        let applying_rules: Vec<ComputedRule> = online_rules
+        let online_rules = context.get_online_rules();
+        let evaluation = context
+            .rule_engine
+            .borrow_mut()
+            .evaluate(online_rules, Instant::now(), |sensor| {
+                context.read_sensor(sensor)
+            });
+        let mut failed_outputs = evaluation.failed_outputs;
+
+        let triggered_rules: Vec<String> = evaluation
+            .applying_rules
             .iter()
-            .filter_map(|rule| {
-                if rule.is_triggered() {
-                    Some(context.compute_rule_actions(rule))
-                } else {
-                    None
-                }
-            })
+            .map(|rule| rule.rule.rule_name().into_owned())
             .collect();
 
-        applying_rules
+        evaluation
+            .applying_rules
             .iter()
             .filter(|&rule| rule.should_log)
             .for_each(|computed_rule| {
-                print_log(computed_rule.rule.when, &computed_rule.rule.sensor);
+                print_log(computed_rule.rule, computed_rule.sensor_value);
             });
 
-        // Combine rules attending to the priorization rules specified
-        // in the configuration.
-        let combined_rules =
-            applying_rules
-                .into_iter()
-                .fold(CombinedRule::default(), |acc, rule| {
-                    let output_values = rule.output_values;
-                    let source_rule = rule.rule;
-
-                    let combined_rule = diff_maps_into(acc.output_values, output_values)
-                        .into_iter()
-                        .map(|(k, v)| {
-                            let newval = match v {
-                                ValueDiff::Left(value) => value,
-                                ValueDiff::Right(value) => {
-                                    CombinedRuleOutputValue::new(source_rule, value)
-                                }
-                                ValueDiff::Both(lvalue, rvalue) => {
-                                    let rvalue = CombinedRuleOutputValue::new(source_rule, rvalue);
-                                    priorization_fun(k.output.priorization.clone())(lvalue, rvalue)
-                                }
-                            };
-                            (k, newval)
-                        })
-                        .collect();
-
-                    CombinedRule::new(combined_rule)
-                });
+        // Manual overrides take precedence over the rules and the
+        // failure policies.
+        failed_outputs.retain(|key, _| !context.is_overridden(&key.output.name));
 
-        for (key, value) in combined_rules.output_values.into_iter() {
+        let mut output_statuses: Vec<control::OutputStatus> = Vec::new();
+        for (key, value) in evaluation.combined.output_values.into_iter() {
             let output = key.output;
 
+            if context.is_overridden(&output.name) {
+                continue;
+            }
+
+            if failed_outputs.contains_key(&key) {
+                // Some of the rules of the output couldn't be
+                // evaluated, so the combined value cannot be trusted.
+                continue;
+            }
+
+            if context.device_forced_full(&output.device.name) {
+                continue;
+            }
+
             if let Some(device) = context.find_device(&output.device.name) {
-                rule_debug!(@ value.rule.when.rule_name();
+                rule_debug!(@ value.rule.rule_name();
                     "Set `{}` to {}.",
                     output.name,
                     value.value
                 );
 
-                device
-                    .write_pwm(output.index, PwmMode::ManualPercent(value.value))
-                    .unwrap(); // FIXME Return a result
+                let written = context.output_value(output, value.value, interval);
+                match context.write_output_value(device, output, written) {
+                    Ok(()) => output_statuses.push(control::OutputStatus::new(
+                        output.name.clone(),
+                        written.value(),
+                        Some(value.rule.rule_name().into_owned()),
+                        false,
+                    )),
+                    Err(err) => {
+                        failed_outputs.insert(key, format!("Cannot write output: {}", err));
+                    }
+                }
             } else {
                 warn!(
                     "Couldn't completely apply rule: Cannot find device `{}`",
@@ -734,6 +1928,32 @@ fn main() -> Result<(), Box<dyn Error>> {
             }
         }
 
-        std::thread::sleep((interval - start_time.elapsed()).max(Duration::default()));
+        context.apply_overrides(Instant::now());
+        context.update_rule_failures(&evaluation.failed_rules);
+        context.update_output_failures(failed_outputs);
+        context.check_stalled_outputs(Instant::now());
+
+        context.update_last_readings(&evaluation.readings);
+        if let Some(recorder) = recorder.as_mut() {
+            let row = context.record_row(&evaluation.readings, &output_statuses);
+            if let Err(err) = recorder.record(&row) {
+                warn!("Cannot record to {}: {}", recorder.path().display(), err);
+            }
+        }
+
+        *context.last_evaluation.borrow_mut() = control::RulesStatus {
+            triggered: triggered_rules,
+            outputs: output_statuses,
+        };
+
+        if let Some(server) = control_server.as_mut() {
+            server.poll(|request| context.handle_control_request(request));
+        }
+
+        if let Some(server) = metrics_server.as_mut() {
+            server.poll(|| context.render_metrics());
+        }
+
+        std::thread::sleep(interval.checked_sub(start_time.elapsed()).unwrap_or_default());
     }
 }
@@ -0,0 +1,258 @@
+//! Minimal HTTP server exposing the state of the program in the
+//! Prometheus text exposition format.
+
+use log::{debug, warn};
+use std::{
+    fmt::Write as _,
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+
+/// Time a client has for sending its request and reading the response
+/// before being disconnected.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum length of the head of a request.
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+
+pub enum MetricKind {
+    Gauge,
+    Counter,
+}
+
+impl MetricKind {
+    fn name(&self) -> &'static str {
+        match self {
+            MetricKind::Gauge => "gauge",
+            MetricKind::Counter => "counter",
+        }
+    }
+}
+
+/// Builds a document in the Prometheus text exposition format.
+pub struct MetricsWriter {
+    buffer: String,
+}
+
+impl MetricsWriter {
+    pub fn new() -> MetricsWriter {
+        MetricsWriter {
+            buffer: String::new(),
+        }
+    }
+
+    /// Starts a new metric family. All the samples of the family must
+    /// be written right after this.
+    pub fn family(&mut self, name: &str, help: &str, kind: MetricKind) {
+        let _ = writeln!(self.buffer, "# HELP {} {}", name, help);
+        let _ = writeln!(self.buffer, "# TYPE {} {}", name, kind.name());
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.buffer.push_str(name);
+        if !labels.is_empty() {
+            self.buffer.push('{');
+            for (i, (label, label_value)) in labels.iter().enumerate() {
+                if i > 0 {
+                    self.buffer.push(',');
+                }
+                let _ = write!(self.buffer, "{}=\"{}\"", label, escape_label(label_value));
+            }
+            self.buffer.push('}');
+        }
+        let _ = writeln!(self.buffer, " {}", value);
+    }
+
+    pub fn finish(self) -> String {
+        self.buffer
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+struct MetricsClient {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+
+    /// Part of the response not written yet, once the request has
+    /// been answered.
+    response: Option<Vec<u8>>,
+    connected_at: Instant,
+}
+
+impl MetricsClient {
+    fn new(stream: TcpStream) -> MetricsClient {
+        MetricsClient {
+            stream,
+            buffer: Vec::new(),
+            response: None,
+            connected_at: Instant::now(),
+        }
+    }
+
+    /// Reads the data available without blocking, and returns the
+    /// request line once the head of the request is complete.
+    fn read_request_line(&mut self) -> io::Result<Option<String>> {
+        let mut chunk = [0u8; 1024];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "Connection closed",
+                    ))
+                }
+                Ok(len) => self.buffer.extend_from_slice(&chunk[..len]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        if !self.buffer.windows(4).any(|window| window == b"\r\n\r\n") {
+            if self.buffer.len() > MAX_REQUEST_LEN {
+                return Err(io::Error::new(ErrorKind::InvalidData, "Request too long"));
+            }
+            return Ok(None);
+        }
+
+        let head = String::from_utf8_lossy(&self.buffer);
+        Ok(head.lines().next().map(|line| line.to_string()))
+    }
+
+    /// Queues the response to the request. It is written by `flush`.
+    fn respond(&mut self, status: &str, content_type: &str, body: &str) {
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        );
+        self.response = Some(response.into_bytes());
+    }
+
+    /// Writes the response until the socket would block. Returns
+    /// whether the whole response has been written.
+    fn flush(&mut self) -> io::Result<bool> {
+        let response = match self.response.as_mut() {
+            Some(response) => response,
+            None => return Ok(false),
+        };
+
+        while !response.is_empty() {
+            match self.stream.write(response) {
+                Ok(0) => return Err(io::Error::new(ErrorKind::WriteZero, "Connection closed")),
+                Ok(len) => {
+                    response.drain(..len);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+/// HTTP server that serves the metrics of the program. It never
+/// blocks, so it can be polled from the main loop of the program.
+pub struct MetricsServer {
+    listener: TcpListener,
+    clients: Vec<MetricsClient>,
+}
+
+impl MetricsServer {
+    pub fn bind(addr: SocketAddr) -> io::Result<MetricsServer> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+
+        Ok(MetricsServer {
+            listener,
+            clients: Vec::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts the pending connections, answers the complete requests
+    /// received so far, and writes the pending responses until the
+    /// sockets would block. The metrics are rendered at most once per
+    /// call, and only if some client requested them.
+    pub fn poll<F: FnOnce() -> String>(&mut self, render: F) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => match stream.set_nonblocking(true) {
+                    Ok(()) => self.clients.push(MetricsClient::new(stream)),
+                    Err(err) => warn!("Cannot set up metrics client: {}", err),
+                },
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    warn!("Cannot accept metrics client: {}", err);
+                    break;
+                }
+            }
+        }
+
+        let mut render = Some(render);
+        let mut rendered: Option<String> = None;
+        for mut client in std::mem::take(&mut self.clients).into_iter() {
+            if client.response.is_none() {
+                let request_line = match client.read_request_line() {
+                    Ok(Some(request_line)) => request_line,
+                    Ok(None) => {
+                        if client.connected_at.elapsed() < REQUEST_TIMEOUT {
+                            self.clients.push(client);
+                        }
+                        continue;
+                    }
+                    Err(err) => {
+                        debug!("Dropped metrics client: {}", err);
+                        continue;
+                    }
+                };
+
+                let mut parts = request_line.split_whitespace();
+                match (parts.next(), parts.next()) {
+                    (Some("GET"), Some("/metrics")) => {
+                        if rendered.is_none() {
+                            rendered = render.take().map(|render| render());
+                        }
+                        client.respond(
+                            "200 OK",
+                            "text/plain; version=0.0.4",
+                            rendered.as_deref().unwrap_or_default(),
+                        );
+                    }
+                    (Some("GET"), _) => {
+                        client.respond("404 Not Found", "text/plain", "Not Found\n")
+                    }
+                    _ => client.respond(
+                        "405 Method Not Allowed",
+                        "text/plain",
+                        "Method Not Allowed\n",
+                    ),
+                }
+            }
+
+            match client.flush() {
+                Ok(true) => {}
+                Ok(false) if client.connected_at.elapsed() < REQUEST_TIMEOUT => {
+                    self.clients.push(client)
+                }
+                Ok(false) => debug!("Dropped metrics client: Timed out writing the response"),
+                Err(err) => debug!("Cannot write metrics response: {}", err),
+            }
+        }
+    }
+}
@@ -0,0 +1,411 @@
+//! Recording of the history of the sensors and outputs to a file,
+//! either as CSV or as JSON lines, with rotation by size.
+
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordFormat {
+    Csv,
+    JsonLines,
+}
+
+impl RecordFormat {
+    pub fn from_name(name: &str) -> Option<RecordFormat> {
+        match name {
+            "csv" => Some(RecordFormat::Csv),
+            "jsonl" => Some(RecordFormat::JsonLines),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format from the extension of the given file,
+    /// defaulting to CSV.
+    pub fn from_path(path: &Path) -> RecordFormat {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("jsonl") | Some("json") => RecordFormat::JsonLines,
+            _ => RecordFormat::Csv,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, new)]
+pub struct RecordedOutput {
+    /// Last value written to the output.
+    pub value: Option<u8>,
+
+    /// What set the value of the output on this iteration, if it has
+    /// been set: the tag of the winning rule, `override` or `stall`.
+    pub rule: Option<String>,
+}
+
+/// State of the sensors and outputs on an iteration of the main loop.
+#[derive(Serialize, Debug, new)]
+pub struct RecordRow {
+    pub time: String,
+    pub sensors: BTreeMap<String, Option<f64>>,
+    pub outputs: BTreeMap<String, RecordedOutput>,
+}
+
+impl RecordRow {
+    fn csv_header(&self) -> String {
+        let mut columns = vec!["time".to_string()];
+        columns.extend(self.sensors.keys().cloned());
+        for name in self.outputs.keys() {
+            columns.push(name.clone());
+            columns.push(format!("{}_rule", name));
+        }
+        columns.join(",")
+    }
+
+    fn csv_line(&self) -> String {
+        fn opt<T: ToString>(value: &Option<T>) -> String {
+            value.as_ref().map(T::to_string).unwrap_or_default()
+        }
+
+        let mut columns = vec![self.time.clone()];
+        columns.extend(self.sensors.values().map(opt));
+        for output in self.outputs.values() {
+            columns.push(opt(&output.value));
+            columns.push(opt(&output.rule));
+        }
+        columns.join(",")
+    }
+}
+
+/// Appends rows to a record file, rotating it once it grows past the
+/// maximum size. Rotated files are renamed by appending `.1`, `.2`,
+/// and so on to the name of the file, the lower the number, the more
+/// recent the file.
+pub struct Recorder {
+    path: PathBuf,
+    format: RecordFormat,
+    max_size: u64,
+    keep: u32,
+    file: Option<File>,
+    size: u64,
+
+    /// Header of the current file, if the format is CSV.
+    header: Option<String>,
+}
+
+impl Recorder {
+    pub fn new(path: PathBuf, format: RecordFormat, max_size: u64, keep: u32) -> Recorder {
+        Recorder {
+            path,
+            format,
+            max_size,
+            keep,
+            file: None,
+            size: 0,
+            header: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn rotated_path(&self, index: u32) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        name.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+
+        if self.keep == 0 {
+            return match fs::remove_file(&self.path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+                _ => Ok(()),
+            };
+        }
+
+        for index in (1..self.keep).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                fs::rename(&from, self.rotated_path(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))
+    }
+
+    /// Opens the record file for appending, recovering the header of
+    /// the file if it already exists.
+    fn open(&mut self) -> io::Result<()> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = file.metadata()?.len();
+        self.header = if self.size > 0 && self.format == RecordFormat::Csv {
+            let mut header = String::new();
+            BufReader::new(File::open(&self.path)?).read_line(&mut header)?;
+            Some(header.trim_end().to_string())
+        } else {
+            None
+        };
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let file = self.file.as_mut().unwrap();
+        file.write_all(line.as_bytes())?;
+        file.write_all(b"\n")?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    pub fn record(&mut self, row: &RecordRow) -> io::Result<()> {
+        if self.file.is_none() {
+            self.open()?;
+        }
+
+        let (header, line) = match self.format {
+            RecordFormat::Csv => (Some(row.csv_header()), row.csv_line()),
+            RecordFormat::JsonLines => (None, serde_json::to_string(row)?),
+        };
+
+        // A CSV file cannot change its columns, so a new file is
+        // started if the sensors or the outputs change.
+        let header_changed = header.is_some() && self.size > 0 && self.header != header;
+        if header_changed || (self.size > 0 && self.size + line.len() as u64 >= self.max_size) {
+            self.rotate()?;
+            self.open()?;
+        }
+
+        if let Some(header) = header {
+            if self.size == 0 {
+                self.write_line(&header)?;
+                self.header = Some(header);
+            }
+        }
+
+        self.write_line(&line)
+    }
+}
+
+/// Formats the given time as an UTC date and time, in a format
+/// spreadsheets understand (`YYYY-MM-DD hh:mm:ss.sss`).
+pub fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let days = (secs / 86400) as i64;
+    let secs_of_day = secs % 86400;
+
+    // Conversion from days since the epoch to a civil date, from
+    // Howard Hinnant's date algorithms.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Parses a time formatted as `format_timestamp` does. The fraction
+/// of the seconds is optional.
+pub fn parse_timestamp(time: &str) -> Option<SystemTime> {
+    let (date, clock) = time.trim().split_once(' ')?;
+
+    let mut date = date.split('-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let mut clock = clock.split(':');
+    let (hour, minute, second) = (
+        clock.next()?.parse::<u64>().ok()?,
+        clock.next()?.parse::<u64>().ok()?,
+        clock.next()?,
+    );
+    if date.next().is_some() || clock.next().is_some() {
+        return None;
+    }
+
+    let (second, fraction) = second.split_once('.').unwrap_or((second, ""));
+    let second = second.parse::<u64>().ok()?;
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+        || fraction.len() > 9
+        || !fraction.chars().all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    let nanos = format!("{:0<9}", fraction).parse::<u32>().ok()?;
+
+    // Conversion from a civil date to days since the epoch, the
+    // inverse of the one done by `format_timestamp`.
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    if days < 0 {
+        return None;
+    }
+
+    let secs = days as u64 * 86400 + hour * 3600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::new(secs, nanos))
+}
+
+/// Parses a size in bytes, optionally followed by a K, M or G suffix.
+pub fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let (number, multiplier) = match size.chars().last()?.to_ascii_uppercase() {
+        'K' => (&size[..size.len() - 1], 1024),
+        'M' => (&size[..size.len() - 1], 1024 * 1024),
+        'G' => (&size[..size.len() - 1], 1024 * 1024 * 1024),
+        _ => (size, 1),
+    };
+
+    number.trim().parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TestDir;
+
+    fn row(time: &str, sensors: &[&str]) -> RecordRow {
+        RecordRow::new(
+            time.to_string(),
+            sensors
+                .iter()
+                .map(|sensor| (sensor.to_string(), Some(1.5)))
+                .collect(),
+            BTreeMap::new(),
+        )
+    }
+
+    fn read(path: &Path) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| line.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn rotate_at_max_size() {
+        let dir = TestDir::new("record-rotate");
+        let path = dir.join("history.csv");
+        // Every row exceeds the maximum size, so each one is written
+        // to a new file.
+        let mut recorder = Recorder::new(path.clone(), RecordFormat::Csv, 1, 2);
+        for time in ["0", "1", "2", "3"].iter() {
+            recorder.record(&row(time, &["a"])).unwrap();
+        }
+
+        assert_eq!(read(&path), ["time,a", "3,1.5"]);
+        assert_eq!(read(&dir.join("history.csv.1")), ["time,a", "2,1.5"]);
+        assert_eq!(read(&dir.join("history.csv.2")), ["time,a", "1,1.5"]);
+        assert!(!dir.join("history.csv.3").exists());
+    }
+
+    #[test]
+    fn rotate_keeping_none() {
+        let dir = TestDir::new("record-keep-none");
+        let path = dir.join("history.jsonl");
+        let mut recorder = Recorder::new(path.clone(), RecordFormat::JsonLines, 1, 0);
+        recorder.record(&row("0", &["a"])).unwrap();
+        recorder.record(&row("1", &["a"])).unwrap();
+
+        let lines = read(&path);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains("\"time\":\"1\""));
+        assert!(!dir.join("history.jsonl.1").exists());
+    }
+
+    #[test]
+    fn rotate_on_header_change() {
+        let dir = TestDir::new("record-header-change");
+        let path = dir.join("history.csv");
+        let mut recorder = Recorder::new(path.clone(), RecordFormat::Csv, 1024, 1);
+        recorder.record(&row("0", &["a"])).unwrap();
+        recorder.record(&row("1", &["a"])).unwrap();
+        recorder.record(&row("2", &["a", "b"])).unwrap();
+
+        assert_eq!(
+            read(&dir.join("history.csv.1")),
+            ["time,a", "0,1.5", "1,1.5"]
+        );
+        assert_eq!(read(&path), ["time,a,b", "2,1.5,1.5"]);
+    }
+
+    #[test]
+    fn append_to_existing_file() {
+        let dir = TestDir::new("record-append");
+        let path = dir.join("history.csv");
+        Recorder::new(path.clone(), RecordFormat::Csv, 1024, 1)
+            .record(&row("0", &["a"]))
+            .unwrap();
+
+        // The header of the existing file is recovered, so it is not
+        // written again, nor the file rotated.
+        let mut recorder = Recorder::new(path.clone(), RecordFormat::Csv, 1024, 1);
+        recorder.record(&row("1", &["a"])).unwrap();
+        assert_eq!(read(&path), ["time,a", "0,1.5", "1,1.5"]);
+        assert!(!dir.join("history.csv.1").exists());
+
+        let mut recorder = Recorder::new(path.clone(), RecordFormat::Csv, 1024, 1);
+        recorder.record(&row("2", &["b"])).unwrap();
+        assert_eq!(read(&path), ["time,b", "2,1.5"]);
+        assert_eq!(
+            read(&dir.join("history.csv.1")),
+            ["time,a", "0,1.5", "1,1.5"]
+        );
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01 00:00:00.000");
+        assert_eq!(
+            format_timestamp(UNIX_EPOCH + Duration::from_millis(1_709_210_096_789)),
+            "2024-02-29 12:34:56.789"
+        );
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("1970-01-01 00:00:00"), Some(UNIX_EPOCH));
+        assert_eq!(
+            parse_timestamp("2024-02-29 12:34:56.789"),
+            Some(UNIX_EPOCH + Duration::from_millis(1_709_210_096_789))
+        );
+        assert_eq!(parse_timestamp("2024-13-01 00:00:00"), None);
+        assert_eq!(parse_timestamp("12.5"), None);
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("10M"), Some(10 * 1024 * 1024));
+        assert_eq!(parse_size("1k"), Some(1024));
+        assert_eq!(parse_size("M"), None);
+        assert_eq!(parse_size("ten"), None);
+    }
+}
@@ -0,0 +1,57 @@
+use libc::{c_int, SIGHUP, SIGINT, SIGTERM};
+use std::io::{Error, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_shutdown_signal(_signal: c_int) {
+    SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn handle_reload_signal(_signal: c_int) {
+    RELOAD_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+fn install_handler(signal: c_int, handler: extern "C" fn(c_int)) -> Result<()> {
+    // SA_RESTART is intentionally not set, so blocking calls like
+    // poll are interrupted and the program can react to the signal
+    // as soon as possible.
+    let errno = unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler as libc::sighandler_t;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(signal, &action, std::ptr::null_mut())
+    };
+
+    if errno < 0 {
+        Err(Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Installs the handlers for the signals that request the program to
+/// terminate (SIGINT and SIGTERM).
+pub fn install_shutdown_handlers() -> Result<()> {
+    install_handler(SIGINT, handle_shutdown_signal)?;
+    install_handler(SIGTERM, handle_shutdown_signal)
+}
+
+/// Installs the handler for the signal that requests the program to
+/// reload its configuration (SIGHUP).
+pub fn install_reload_handler() -> Result<()> {
+    install_handler(SIGHUP, handle_reload_signal)
+}
+
+/// Returns whether the program has received a signal requesting it
+/// to reload its configuration since the last call to this function.
+pub fn reload_requested() -> bool {
+    RELOAD_REQUESTED.swap(false, Ordering::SeqCst)
+}
+
+/// Returns whether the program has received a signal requesting it
+/// to terminate.
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN_REQUESTED.load(Ordering::SeqCst)
+}
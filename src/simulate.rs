@@ -0,0 +1,289 @@
+//! Offline simulation of a thermal program. The values of the sensors
+//! are read from a CSV trace instead of from the devices, and the
+//! value each output would have taken on each row of the trace is
+//! written out as CSV.
+//!
+//! The first column of the trace is the timestamp of each row, and
+//! the rest of columns are the values of the sensors, named after
+//! them on the header of the trace. Temperatures are expressed in
+//! degrees Celsius, and fan speeds in RPM. An empty value simulates a
+//! failed read of the sensor.
+//!
+//! The timestamps are either a number of seconds, or a date and time
+//! as written on the record files (`YYYY-MM-DD hh:mm:ss.sss`). They
+//! are required for simulating the rules with a FOR or a RELEASE
+//! AFTER clause, and the outputs with ramps or a spin up.
+//!
+//! The values set by the rules go through the limits, ramps and spin
+//! up of the outputs, as on the actual devices, taking the time
+//! between the rows of the trace as the time between evaluations.
+
+use crate::config::{ast, checker::model as cmodel, SymbolOutput, SymbolSensor};
+use crate::engine::{OutputShaper, RuleEngine, SensorResult};
+use crate::record;
+use crate::types::{Percent, Rpm, SensorValue, TempCelsius};
+use guard::guard;
+use std::{
+    collections::HashMap,
+    error::Error,
+    io::{self, BufRead, BufReader, Write},
+    rc::Rc,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+struct TraceRow {
+    line: usize,
+    time: String,
+
+    /// Time of the row, relative to an arbitrary origin, if the
+    /// timestamp of the row can be parsed.
+    at: Option<Duration>,
+    values: Vec<Option<f64>>,
+}
+
+fn parse_time(time: &str) -> Option<Duration> {
+    match time.parse::<f64>() {
+        Ok(secs) if secs.is_finite() && secs >= 0.0 => Some(Duration::from_secs_f64(secs)),
+        Ok(_) => None,
+        Err(_) => record::parse_timestamp(time)?
+            .duration_since(UNIX_EPOCH)
+            .ok(),
+    }
+}
+
+struct Trace {
+    /// Index of the column of each sensor in the rows of the trace,
+    /// indexed by sensor name.
+    columns: HashMap<String, usize>,
+    rows: Vec<TraceRow>,
+}
+
+impl Trace {
+    fn parse<R: BufRead>(
+        program: &cmodel::ThermalProgram,
+        reader: R,
+    ) -> Result<Trace, Box<dyn Error>> {
+        let mut lines = reader.lines().enumerate();
+
+        let header = match lines.next() {
+            Some((_, header)) => header?,
+            None => return Err("The trace is empty".into()),
+        };
+
+        let mut columns = HashMap::new();
+        for (index, name) in header.split(',').skip(1).enumerate() {
+            let name = name.trim();
+            if program
+                .symbol_table
+                .require_type::<SymbolSensor>(name)
+                .is_err()
+            {
+                return Err(format!("Unknown sensor `{}` on the header of the trace", name).into());
+            }
+            columns.insert(name.to_string(), index);
+        }
+
+        let mut rows = Vec::new();
+        for (index, line) in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let mut fields = line.split(',').map(str::trim);
+            let time = fields.next().unwrap_or_default().to_string();
+            let values = fields
+                .map(|field| {
+                    if field.is_empty() {
+                        Ok(None)
+                    } else {
+                        field.parse::<f64>().map(Some).map_err(|err| {
+                            format!("Invalid value `{}` on line {}: {}", field, index + 1, err)
+                        })
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            rows.push(TraceRow {
+                line: index + 1,
+                at: parse_time(&time),
+                time,
+                values,
+            });
+        }
+
+        Ok(Trace { columns, rows })
+    }
+
+    fn read_sensor(&self, row: &TraceRow, sensor: &SymbolSensor) -> SensorResult {
+        let value = self
+            .columns
+            .get(&sensor.name)
+            .and_then(|&column| row.values.get(column).copied().flatten());
+
+        guard!(let Some(value) = value else {
+            return Err(Rc::new(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No value on line {} of the trace", row.line),
+            )));
+        });
+
+        Ok(match sensor.sensor_type {
+            ast::SensorType::Termistor => {
+                SensorValue::Temp(TempCelsius::from_mcelsius((value * 1000.0).round() as i32))
+            }
+            ast::SensorType::Fan => SensorValue::Fan(Rpm::from_rpm(value.max(0.0).round() as u32)),
+        })
+    }
+}
+
+/// Value of an output on the current step of the simulation.
+enum SimulatedOutput {
+    /// No rule has set the output yet.
+    Unset,
+    Set {
+        value: u8,
+        rule: String,
+    },
+    Failed,
+}
+
+/// Runs the given program against the trace, writing the value of
+/// each output on each row of the trace to `output`. Outputs that no
+/// rule sets on a row keep their previous value, as they would on the
+/// actual devices.
+pub fn run<R: BufRead, W: Write>(
+    program: &cmodel::ThermalProgram,
+    trace: R,
+    mut output: W,
+) -> Result<(), Box<dyn Error>> {
+    let trace = Trace::parse(program, trace)?;
+
+    let mut outputs: Vec<&Rc<SymbolOutput>> = program
+        .symbol_table
+        .get_all_symbols_of_type::<SymbolOutput>();
+    outputs.sort_by(|a, b| a.name.cmp(&b.name));
+
+    write!(output, "time")?;
+    for symbol in outputs.iter() {
+        write!(output, ",{},{}_rule", symbol.name, symbol.name)?;
+    }
+    writeln!(output)?;
+
+    let timed = program
+        .rules
+        .iter()
+        .any(|rule| !rule.hold.is_zero() || !rule.release.is_zero())
+        || outputs.iter().any(|output| {
+            output.ramp_up.is_some()
+                || output.ramp_down.is_some()
+                || output.limits.spin_up.is_some()
+        });
+    let start = Instant::now();
+    let origin = trace.rows.first().and_then(|row| row.at);
+
+    let mut engine = RuleEngine::default();
+    let mut shaper = OutputShaper::default();
+    let mut state: HashMap<&str, SimulatedOutput> = HashMap::new();
+
+    // Last value of each output, as it would have been written.
+    let mut written: HashMap<&str, Percent> = HashMap::new();
+    let mut previous: Option<Instant> = None;
+
+    for row in trace.rows.iter() {
+        let now = match (row.at, origin) {
+            (Some(at), Some(origin)) => start + at.checked_sub(origin).unwrap_or_default(),
+            _ if timed => {
+                return Err(format!(
+                    "Invalid time `{}` on line {} of the trace. Rules with FOR or RELEASE AFTER, \
+                     and outputs with ramps or a spin up, require it to be a number of seconds \
+                     or a `YYYY-MM-DD hh:mm:ss` timestamp",
+                    row.time, row.line
+                )
+                .into())
+            }
+            _ => start,
+        };
+        let interval = previous.map_or_else(Duration::default, |previous| {
+            now.saturating_duration_since(previous)
+        });
+        previous = Some(now);
+
+        let evaluation = engine.evaluate(program.rules.iter(), now, |sensor| {
+            trace.read_sensor(row, sensor)
+        });
+
+        for (key, value) in evaluation.combined.output_values.iter() {
+            // As on the devices, failed outputs are not written.
+            if evaluation.failed_outputs.contains_key(key) {
+                continue;
+            }
+
+            let name = key.output.name.as_str();
+            let shaped = shaper.shape(
+                key.output,
+                value.value,
+                written.get(name).copied(),
+                interval,
+                now,
+            );
+            written.insert(name, shaped);
+            state.insert(
+                name,
+                SimulatedOutput::Set {
+                    value: shaped.value(),
+                    rule: value.rule.rule_name().into_owned(),
+                },
+            );
+        }
+
+        for key in evaluation.failed_outputs.keys() {
+            state.insert(key.output.name.as_str(), SimulatedOutput::Failed);
+        }
+
+        write!(output, "{}", row.time)?;
+        for symbol in outputs.iter() {
+            match state
+                .get(symbol.name.as_str())
+                .unwrap_or(&SimulatedOutput::Unset)
+            {
+                SimulatedOutput::Unset => write!(output, ",,")?,
+                SimulatedOutput::Set { value, rule } => write!(output, ",{},{}", value, rule)?,
+                SimulatedOutput::Failed => write!(output, ",,FAILED")?,
+            }
+        }
+        writeln!(output)?;
+    }
+
+    output.flush()?;
+    Ok(())
+}
+
+/// Runs the simulation reading the trace from the given file, and
+/// writing the result to the given file, or to the standard output if
+/// none is given.
+pub fn run_files(
+    program: &cmodel::ThermalProgram,
+    trace_path: &str,
+    output_path: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let trace = BufReader::new(
+        std::fs::File::open(trace_path)
+            .map_err(|err| format!("Cannot open trace {}: {}", trace_path, err))?,
+    );
+
+    match output_path {
+        Some(path) => run(
+            program,
+            trace,
+            io::BufWriter::new(
+                std::fs::File::create(path)
+                    .map_err(|err| format!("Cannot create {}: {}", path, err))?,
+            ),
+        ),
+        None => {
+            let stdout = io::stdout();
+            run(program, trace, stdout.lock())
+        }
+    }
+}
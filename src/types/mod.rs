@@ -1,7 +1,11 @@
 mod percent;
+mod rpm;
+mod sensor;
 mod temp;
 mod units;
 
 pub use percent::*;
+pub use rpm::*;
+pub use sensor::*;
 pub use temp::*;
 pub use units::*;
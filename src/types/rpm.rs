@@ -0,0 +1,32 @@
+use super::{Measure, MeasureUnit};
+use std::fmt::{Debug, Display};
+
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub struct RevolutionsPerMinute;
+impl MeasureUnit for RevolutionsPerMinute {
+    type Holder = u32;
+}
+
+pub type Rpm = Measure<RevolutionsPerMinute>;
+
+impl Measure<RevolutionsPerMinute> {
+    pub fn from_rpm(value: u32) -> Rpm {
+        Measure::new(value)
+    }
+
+    pub fn rpm(self) -> u32 {
+        self.raw_value()
+    }
+}
+
+impl Display for Measure<RevolutionsPerMinute> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} RPM", self.raw_value())
+    }
+}
+
+impl Debug for Measure<RevolutionsPerMinute> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        <Rpm as Display>::fmt(self, f)
+    }
+}
@@ -0,0 +1,72 @@
+use super::{Rpm, TempCelsius};
+use std::{
+    cmp::Ordering,
+    convert::TryFrom,
+    fmt::{Debug, Display},
+};
+
+/// Value read from a sensor, in the measure that corresponds to the
+/// kind of the sensor. Values of different kinds are not comparable.
+#[derive(Clone, Copy, PartialEq)]
+pub enum SensorValue {
+    Temp(TempCelsius),
+    Fan(Rpm),
+}
+
+impl SensorValue {
+    /// Returns the value as an integer in the base unit of its
+    /// measure: millicelsius for temperatures and RPM for fans.
+    pub fn raw(self) -> i32 {
+        match self {
+            SensorValue::Temp(temp) => temp.mcelsius(),
+            SensorValue::Fan(rpm) => i32::try_from(rpm.rpm()).unwrap_or(i32::MAX),
+        }
+    }
+
+    /// Returns the value in the unit it is usually displayed in:
+    /// degrees Celsius for temperatures and RPM for fans.
+    pub fn value(self) -> f64 {
+        match self {
+            SensorValue::Temp(temp) => temp.mcelsius() as f64 / 1000.0,
+            SensorValue::Fan(rpm) => rpm.rpm() as f64,
+        }
+    }
+
+    /// Returns a value of the same kind, displaced by the given amount
+    /// of base units, saturating at the bounds of the base unit.
+    pub fn offset(self, delta: i32) -> SensorValue {
+        match self {
+            SensorValue::Temp(temp) => SensorValue::Temp(TempCelsius::from_mcelsius(
+                temp.mcelsius().saturating_add(delta),
+            )),
+            SensorValue::Fan(_) => {
+                SensorValue::Fan(Rpm::from_rpm(self.raw().saturating_add(delta).max(0) as u32))
+            }
+        }
+    }
+}
+
+impl PartialOrd for SensorValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (SensorValue::Temp(left), SensorValue::Temp(right)) => left.partial_cmp(right),
+            (SensorValue::Fan(left), SensorValue::Fan(right)) => left.partial_cmp(right),
+            _ => None,
+        }
+    }
+}
+
+impl Display for SensorValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SensorValue::Temp(temp) => Display::fmt(temp, f),
+            SensorValue::Fan(rpm) => Display::fmt(rpm, f),
+        }
+    }
+}
+
+impl Debug for SensorValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
@@ -16,7 +16,6 @@ impl MeasureUnit for Fahrenheit {
 // Celsius units represented as millicelsius, to prevent precision loss.
 pub type TempCelsius = Measure<Celsius>;
 
-#[allow(unused)] // FIXME Remove when Fahrenheit support is added.
 pub type TempFahrenheit = Measure<Fahrenheit>;
 
 impl Measure<Celsius> {
@@ -24,35 +23,50 @@ impl Measure<Celsius> {
         Measure::new(value)
     }
 
+    #[cfg(test)]
     pub fn from_celsius(value: i32) -> TempCelsius {
         Measure::new(value * 1000)
     }
 
-    pub fn celsius(self) -> i32 {
-        self.raw_value() / 1000
-    }
-
     pub fn mcelsius(self) -> i32 {
         self.raw_value()
     }
 }
 
-#[allow(unused)] // FIXME Remove when Fahrenheit support is added.
 impl Measure<Fahrenheit> {
+    pub fn from_fahrenheit(value: f32) -> TempFahrenheit {
+        Measure::new(value)
+    }
+
     pub fn value(self) -> f32 {
         self.raw_value()
     }
 }
 
+impl From<TempFahrenheit> for TempCelsius {
+    /// Converts the temperature, rounding it to the nearest
+    /// millicelsius.
+    fn from(temp: TempFahrenheit) -> TempCelsius {
+        TempCelsius::from_mcelsius(((temp.value() - 32.0) * 5000.0 / 9.0).round() as i32)
+    }
+}
+
+impl From<TempCelsius> for TempFahrenheit {
+    fn from(temp: TempCelsius) -> TempFahrenheit {
+        TempFahrenheit::from_fahrenheit(temp.mcelsius() as f32 * 9.0 / 5000.0 + 32.0)
+    }
+}
+
 impl Display for Measure<Celsius> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let int = self.raw_value() / 1000;
-        let dec = self.raw_value() % 1000;
+        let sign = if self.raw_value() < 0 { "-" } else { "" };
+        let int = (self.raw_value() / 1000).abs();
+        let dec = (self.raw_value() % 1000).abs();
 
         if dec == 0 {
-            write!(f, "{} °C", int)
+            write!(f, "{}{} °C", sign, int)
         } else {
-            write!(f, "{}.{:03} °C", int, dec)
+            write!(f, "{}{}.{:03} °C", sign, int, dec)
         }
     }
 }
@@ -62,3 +76,46 @@ impl Debug for Measure<Celsius> {
         <TempCelsius as Display>::fmt(self, f)
     }
 }
+
+impl Display for Measure<Fahrenheit> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} °F", self.raw_value())
+    }
+}
+
+impl Debug for Measure<Fahrenheit> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        <TempFahrenheit as Display>::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fahrenheit_to_celsius() {
+        assert_eq!(
+            TempCelsius::from(TempFahrenheit::from_fahrenheit(212.0)),
+            TempCelsius::from_celsius(100)
+        );
+        assert_eq!(
+            TempCelsius::from(TempFahrenheit::from_fahrenheit(85.0)),
+            TempCelsius::from_mcelsius(29_444)
+        );
+    }
+
+    #[test]
+    fn celsius_to_fahrenheit() {
+        assert_eq!(
+            TempFahrenheit::from(TempCelsius::from_celsius(-40)),
+            TempFahrenheit::from_fahrenheit(-40.0)
+        );
+    }
+
+    #[test]
+    fn display_negative_celsius() {
+        assert_eq!(TempCelsius::from_mcelsius(-500).to_string(), "-0.500 °C");
+        assert_eq!(TempCelsius::from_celsius(-5).to_string(), "-5 °C");
+    }
+}
@@ -2,7 +2,10 @@ use libc::{c_int, poll, pollfd, POLLIN};
 use std::convert::TryInto;
 use std::io::Result;
 use std::os::unix::io::AsRawFd;
-use std::{io::Error, time::Duration};
+use std::{
+    io::{Error, ErrorKind},
+    time::Duration,
+};
 use udev::{Event, MonitorSocket};
 
 #[derive(Copy, Clone)]
@@ -44,7 +47,14 @@ impl UdevPoller {
     fn do_poll(&mut self, raw_timeout: c_int) -> Result<Vec<Event>> {
         let errno = unsafe { poll((&mut self.pollfd) as *mut pollfd, 1, raw_timeout) };
         if errno < 0 {
-            Err(Error::last_os_error())
+            let error = Error::last_os_error();
+            if error.kind() == ErrorKind::Interrupted {
+                // Interrupted by a signal. Let the caller handle it
+                // as if no events were received.
+                Ok(Vec::new())
+            } else {
+                Err(error)
+            }
         } else {
             let mut events = Vec::new();
             while let Some(event) = self.monitor.next() {
@@ -1,2 +1,7 @@
 mod biggernum;
 pub use biggernum::*;
+
+#[cfg(test)]
+mod testdir;
+#[cfg(test)]
+pub use testdir::*;
@@ -0,0 +1,50 @@
+use std::path::{Path, PathBuf};
+
+/// Temporary directory for tests, e.g. laid out as the sysfs directory
+/// of a device. It is removed when dropped, even if the test fails.
+pub struct TestDir {
+    path: PathBuf,
+}
+
+impl TestDir {
+    /// Creates an empty directory, unique for the given name within
+    /// the running process.
+    pub fn new(name: &str) -> TestDir {
+        let path = std::env::temp_dir().join(format!("fancontrol-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TestDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, name: P) -> PathBuf {
+        self.path.join(name)
+    }
+
+    /// Writes an attribute as the kernel exposes it, followed by a
+    /// newline. Creates its parent directories if needed.
+    pub fn write_attr<P: AsRef<Path>>(&self, attr: P, value: &str) {
+        let path = self.join(attr);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).unwrap();
+        }
+        std::fs::write(path, format!("{}\n", value)).unwrap();
+    }
+
+    /// Reads an attribute, without its trailing whitespace.
+    pub fn read_attr<P: AsRef<Path>>(&self, attr: P) -> String {
+        std::fs::read_to_string(self.join(attr))
+            .unwrap()
+            .trim_end()
+            .to_string()
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}